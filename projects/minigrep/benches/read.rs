@@ -0,0 +1,93 @@
+//! Compares memory-mapped and buffered reading on a generated file.
+//!
+//! Run with `cargo bench --bench read`. The input size defaults to 2G and can
+//! be changed with `MINIGREP_BENCH_SIZE` (e.g. `512M`, `4G`), the number of
+//! runs per strategy with `MINIGREP_BENCH_RUNS`. The generated file goes to
+//! the temporary directory, or `MINIGREP_BENCH_DIR` when set, and is removed
+//! afterwards.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use minigrep::Match;
+use minigrep::args::parse_size;
+use minigrep::input::{InputOptions, ReadStrategy};
+use minigrep::searcher::{Searcher, Sink};
+
+const NEEDLE: &str = "needle";
+
+/// Counts the selected lines without printing them.
+struct Count(u64);
+
+impl Sink for Count {
+    fn matched(&mut self, _: &Searcher, _: &Match) -> io::Result<bool> {
+        self.0 += 1;
+        Ok(true)
+    }
+}
+
+fn main() {
+    let size = match env::var("MINIGREP_BENCH_SIZE") {
+        Ok(size) => parse_size(&size).unwrap_or_else(|| {
+            eprintln!("invalid MINIGREP_BENCH_SIZE '{}'", size);
+            process::exit(2);
+        }),
+        Err(_) => 2 << 30,
+    };
+    let runs: u32 = env::var("MINIGREP_BENCH_RUNS").ok().and_then(|runs| runs.parse().ok()).unwrap_or(3);
+    let dir = env::var_os("MINIGREP_BENCH_DIR").map(PathBuf::from).unwrap_or_else(env::temp_dir);
+    let path = dir.join(format!("minigrep-bench-{}.txt", process::id()));
+
+    println!("generating {} bytes in {}", size, path.display());
+    let expected = generate(&path, size).expect("couldn't generate input");
+
+    for (name, strategy) in [("buffered", ReadStrategy::Buffered), ("mmap", ReadStrategy::Mmap)] {
+        let best = (0..runs.max(1))
+            .map(|_| search(&path, strategy, expected))
+            .min()
+            .unwrap();
+        let throughput = size as f64 / best.as_secs_f64() / (1024.0 * 1024.0);
+        println!("{:<10} {:>10.3?} {:>10.1} MiB/s", name, best, throughput);
+    }
+
+    fs::remove_file(&path).expect("couldn't remove input");
+}
+
+/// Writes about `size` bytes of text with a match every 10,000 lines and
+/// returns the number of matching lines.
+fn generate(path: &Path, size: u64) -> io::Result<u64> {
+    let mut out = BufWriter::with_capacity(1 << 20, File::create(path)?);
+    let (mut written, mut line, mut matches) = (0, 0u64, 0);
+
+    while written < size {
+        let text = if line % 10_000 == 9_999 {
+            matches += 1;
+            format!("{} a {} in the haystack\n", line, NEEDLE)
+        } else {
+            format!("{} the quick brown fox jumps over the lazy dog\n", line)
+        };
+        out.write_all(text.as_bytes())?;
+        written += text.len() as u64;
+        line += 1;
+    }
+
+    out.flush()?;
+    Ok(matches)
+}
+
+fn search(path: &Path, strategy: ReadStrategy, expected: u64) -> Duration {
+    let input = InputOptions { strategy, ..InputOptions::default() };
+    let searcher = Searcher::builder().pattern(NEEDLE).input(input).build().unwrap();
+    let mut count = Count(0);
+
+    let start = Instant::now();
+    searcher.search_path(path, &mut count).expect("search failed");
+    let elapsed = start.elapsed();
+
+    assert_eq!(expected, count.0);
+    elapsed
+}
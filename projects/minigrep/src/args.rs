@@ -0,0 +1,710 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+
+use crate::{CaseMode, Config, MinigrepError};
+use crate::defaults::{self, Defaults};
+use crate::input::{self, Decompress, ReadStrategy};
+use crate::printer::{ColorChoice, OutputMode};
+use crate::searcher::BinaryMode;
+
+pub const USAGE: &str = "\
+Usage: minigrep [OPTIONS] QUERY [FILE]...
+       minigrep [OPTIONS] -e QUERY... [-f PATTERN_FILE]... [FILE]...
+       minigrep index build|update [DIR]
+
+Search for QUERY in each FILE. Directories are searched recursively,
+skipping hidden files, binary files and files ignored by .gitignore or
+.ignore.
+With no FILE, or when FILE is -, read standard input. Files compressed with
+gzip, zstd or bzip2 are decompressed, detected by extension or contents.
+
+'minigrep index build DIR' writes a trigram index of DIR to
+DIR/.minigrep-index, and 'minigrep index update DIR' refreshes it, reading
+only new and changed files. Searching DIR with --index then skips the files
+the index rules out; files changed since are always searched. Use
+'minigrep -- index build' to search for 'index' in a file named 'build'.
+
+Options:
+  -i, --ignore-case          Search case-insensitively
+  -s, --case-sensitive       Search case-sensitively (overrides CASE_INSENSITIVE)
+  -S, --smart-case           Search case-insensitively if QUERY is all lowercase
+      --regex                Treat QUERY as a regular expression
+  -F, --fixed-strings        Treat QUERY as plain text (the default)
+  -w, --word-regexp          Only match whole words
+  -x, --line-regexp          Only match whole lines
+  -U, --multiline            Let matches span lines; each match is printed as
+                             the block of lines it covers
+      --fuzzy K              Match QUERY with up to K typos: inserted, deleted
+                             or replaced characters
+      --sort-by-distance     With --fuzzy, print each file's closest matches
+                             first
+  -e, --regexp QUERY         Search for QUERY; may be repeated
+  -f, --file PATTERN_FILE    Search for each line of PATTERN_FILE
+  -n, --line-number          Prefix each line with its line number
+  -A, --after-context NUM    Print NUM lines of trailing context
+  -B, --before-context NUM   Print NUM lines of leading context
+  -C, --context NUM          Print NUM lines of leading and trailing context
+  -v, --invert-match         Select non-matching lines
+  -c, --count                Print only a count of selected lines per file
+  -l, --files-with-matches   Print only names of files with selected lines
+  -L, --files-without-match  Print only names of files without selected lines
+  -q, --quiet                Print nothing; exit on the first selected line
+  -m, --max-count NUM        Stop searching a file after NUM selected lines
+      --max-columns NUM      Truncate printed lines longer than NUM characters
+      --max-filesize SIZE    Skip files larger than SIZE bytes; K, M and G
+                             suffixes are accepted
+      --json                 Print results as JSON Lines
+  -r, --replace TEXT         Print selected lines with each match replaced by
+                             TEXT; with --regex, $1 and ${name} expand groups
+      --in-place             Also write the replacements back to the files
+      --backup SUFFIX        With --in-place, keep originals as FILE+SUFFIX
+  -g, --glob GLOB            Only search files matching GLOB; !GLOB excludes
+      --hidden               Search hidden files and directories
+      --no-ignore            Don't respect .gitignore and .ignore files
+  -a, --text                 Search binary files as if they were text
+      --binary WHEN          Binary files: skip (default), text, or matches to
+                             print only 'Binary file FILE matches'
+  -E, --encoding NAME        Decode input as NAME, e.g. utf-16le, latin1 or
+                             shift_jis (default: by BOM, else UTF-8)
+  -z, --decompress           Require input to be gzip, zstd or bzip2 compressed
+      --no-decompress        Search compressed files without decompressing them
+      --mmap                 Memory-map every regular file (default: only
+                             files of 64 MiB or more)
+      --no-mmap              Never memory-map files
+  -j, --threads NUM          Search NUM files at a time (0: one per CPU)
+      --color WHEN           Highlight matches: auto (default), always or never
+      --no-messages          Don't report files that can't be read
+      --index                Use the index of each DIR to skip files
+      --watch                Keep running and print the selected lines
+                             appended to each FILE, like tail -f | grep
+  -h, --help                 Print this help and exit
+  -V, --version              Print version information and exit
+
+Defaults are read from ~/.config/minigrep/config.toml (or the file named by
+MINIGREP_CONFIG), then from the MINIGREP_COLOR and MINIGREP_THREADS
+environment variables; options given on the command line take precedence.
+Setting the CASE_INSENSITIVE environment variable acts like -i.
+
+Files that can't be read are reported and skipped. Exit status is 2 if an
+error occurred, otherwise 0 if a line (or with -L, a file) was selected and 1
+if none was.";
+
+/// What the command line asked minigrep to do.
+pub enum Command {
+    Search(Box<Config>),
+    /// Build, or with `update` refresh, the trigram index of `root`.
+    Index { root: PathBuf, update: bool },
+    Help,
+    Version,
+}
+
+/// Parses the command line, including the program name in the first position,
+/// on top of the defaults from the config file and environment.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, MinigrepError> {
+    let defaults = defaults::load().map_err(MinigrepError::Usage)?;
+    parse_with_defaults(args, &defaults).map_err(MinigrepError::Usage)
+}
+
+/// What has been parsed so far.
+struct State {
+    config: Config,
+    case: Option<CaseMode>,
+    patterns: Option<Vec<String>>,
+    positional: Vec<String>,
+}
+
+fn parse_with_defaults<I>(args: I, defaults: &[Defaults]) -> Result<Command, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let args: Vec<String> = args.into_iter().skip(1).collect();
+    if let [command, action, root @ ..] = args.as_slice() {
+        if command == "index" && (action == "build" || action == "update") && root.len() <= 1 {
+            let root = PathBuf::from(root.first().map_or(".", String::as_str));
+            return Ok(Command::Index { root, update: action == "update" });
+        }
+    }
+
+    let mut state = State { config: Config::default(), case: None, patterns: None, positional: Vec::new() };
+
+    for default in defaults {
+        let command = parse_options(default.args.iter().cloned(), &mut state)
+            .map_err(|err| format!("{}: {}", default.source, err))?;
+        if let Some(command) = command {
+            return Ok(command);
+        }
+        if let Some(arg) = state.positional.first() {
+            return Err(format!("{}: expected only options, got '{}'", default.source, arg));
+        }
+    }
+
+    if let Some(command) = parse_options(args.into_iter(), &mut state)? {
+        return Ok(command);
+    }
+
+    let State { mut config, case, patterns, positional } = state;
+    let mut positional = positional.into_iter();
+
+    config.patterns = match patterns {
+        Some(patterns) => patterns,
+        None => match positional.next() {
+            Some(arg) => vec![arg],
+            None => return Err(String::from("Didn't get a query string")),
+        },
+    };
+
+    config.filenames = positional.collect();
+    if config.filenames.is_empty() {
+        config.filenames.push(String::from("-"));
+    }
+
+    config.case = case.unwrap_or(CaseMode::Sensitive);
+
+    if config.in_place && config.replacement.is_none() {
+        return Err(String::from("--in-place requires --replace"));
+    }
+    if config.in_place && config.invert_match {
+        return Err(String::from("--in-place can't be used with --invert-match"));
+    }
+    if config.in_place && config.multiline {
+        return Err(String::from("--in-place can't be used with --multiline"));
+    }
+    if config.in_place && !matches!(config.output, OutputMode::Lines | OutputMode::Json) {
+        // The other modes print no lines to preview, and most stop a search
+        // at the first selected line, leaving files or lines unrewritten.
+        return Err(String::from("--in-place can't be used with --count, --files-with-matches, --files-without-match or --quiet"));
+    }
+    if config.in_place && config.input.encoding.is_some() {
+        // Lines are written back as UTF-8, which would re-encode the file.
+        return Err(String::from("--in-place can't be used with --encoding"));
+    }
+    if config.backup_suffix.is_some() && !config.in_place {
+        return Err(String::from("--backup requires --in-place"));
+    }
+    if config.fuzzy.is_some() && config.regex {
+        return Err(String::from("--fuzzy can't be used with --regex"));
+    }
+    if config.sort_by_distance {
+        if config.fuzzy.is_none() {
+            return Err(String::from("--sort-by-distance requires --fuzzy"));
+        }
+        if config.before_context > 0 || config.after_context > 0 {
+            return Err(String::from("--sort-by-distance can't be used with context options"));
+        }
+    }
+    if config.watch {
+        if config.in_place || config.index {
+            return Err(String::from("--watch can't be used with --in-place or --index"));
+        }
+        if !matches!(config.output, OutputMode::Lines | OutputMode::Json) {
+            return Err(String::from("--watch can't be used with --count, --files-with-matches, --files-without-match or --quiet"));
+        }
+        if config.filenames.iter().any(|name| name == "-") {
+            return Err(String::from("--watch can't watch standard input"));
+        }
+    }
+
+    Ok(Command::Search(Box::new(config)))
+}
+
+/// Parses options into `state`, collecting positional arguments. Returns a
+/// command when an option such as `--help` ends parsing.
+fn parse_options<I: Iterator<Item = String>>(mut args: I, state: &mut State) -> Result<Option<Command>, String> {
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            state.positional.extend(args.by_ref());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let spec = find_option(|spec| spec.long == name)
+                .ok_or_else(|| format!("unknown option '--{}'", name))?;
+
+            let value = match (spec.takes_value, value) {
+                (true, Some(value)) => Some(value),
+                (true, None) => Some(required_value(&mut args, &arg)?),
+                (false, Some(_)) => return Err(format!("option '--{}' doesn't take a value", name)),
+                (false, None) => None,
+            };
+            if let Some(command) = apply(spec, value, state)? {
+                return Ok(Some(command));
+            }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            for (i, short) in arg.char_indices().skip(1) {
+                let spec = find_option(|spec| spec.short == Some(short))
+                    .ok_or_else(|| format!("unknown option '-{}'", short))?;
+
+                let value = if spec.takes_value {
+                    let rest = &arg[i + short.len_utf8()..];
+                    if rest.is_empty() {
+                        Some(required_value(&mut args, &format!("-{}", short))?)
+                    } else {
+                        Some(rest.to_string())
+                    }
+                } else {
+                    None
+                };
+
+                let done = value.is_some();
+                if let Some(command) = apply(spec, value, state)? {
+                    return Ok(Some(command));
+                }
+                if done {
+                    break;
+                }
+            }
+        } else {
+            state.positional.push(arg);
+        }
+    }
+
+    Ok(None)
+}
+
+struct OptionSpec {
+    short: Option<char>,
+    long: &'static str,
+    takes_value: bool,
+}
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec { short: Some('i'), long: "ignore-case", takes_value: false },
+    OptionSpec { short: Some('s'), long: "case-sensitive", takes_value: false },
+    OptionSpec { short: Some('S'), long: "smart-case", takes_value: false },
+    OptionSpec { short: None, long: "regex", takes_value: false },
+    OptionSpec { short: Some('F'), long: "fixed-strings", takes_value: false },
+    OptionSpec { short: Some('w'), long: "word-regexp", takes_value: false },
+    OptionSpec { short: Some('x'), long: "line-regexp", takes_value: false },
+    OptionSpec { short: Some('U'), long: "multiline", takes_value: false },
+    OptionSpec { short: None, long: "fuzzy", takes_value: true },
+    OptionSpec { short: None, long: "sort-by-distance", takes_value: false },
+    OptionSpec { short: Some('e'), long: "regexp", takes_value: true },
+    OptionSpec { short: Some('f'), long: "file", takes_value: true },
+    OptionSpec { short: Some('n'), long: "line-number", takes_value: false },
+    OptionSpec { short: Some('A'), long: "after-context", takes_value: true },
+    OptionSpec { short: Some('B'), long: "before-context", takes_value: true },
+    OptionSpec { short: Some('C'), long: "context", takes_value: true },
+    OptionSpec { short: Some('v'), long: "invert-match", takes_value: false },
+    OptionSpec { short: Some('c'), long: "count", takes_value: false },
+    OptionSpec { short: Some('l'), long: "files-with-matches", takes_value: false },
+    OptionSpec { short: Some('L'), long: "files-without-match", takes_value: false },
+    OptionSpec { short: Some('q'), long: "quiet", takes_value: false },
+    OptionSpec { short: None, long: "json", takes_value: false },
+    OptionSpec { short: Some('m'), long: "max-count", takes_value: true },
+    OptionSpec { short: None, long: "max-columns", takes_value: true },
+    OptionSpec { short: None, long: "max-filesize", takes_value: true },
+    OptionSpec { short: Some('r'), long: "replace", takes_value: true },
+    OptionSpec { short: None, long: "in-place", takes_value: false },
+    OptionSpec { short: None, long: "backup", takes_value: true },
+    OptionSpec { short: Some('g'), long: "glob", takes_value: true },
+    OptionSpec { short: None, long: "hidden", takes_value: false },
+    OptionSpec { short: None, long: "no-ignore", takes_value: false },
+    OptionSpec { short: Some('a'), long: "text", takes_value: false },
+    OptionSpec { short: None, long: "binary", takes_value: true },
+    OptionSpec { short: Some('E'), long: "encoding", takes_value: true },
+    OptionSpec { short: Some('z'), long: "decompress", takes_value: false },
+    OptionSpec { short: None, long: "no-decompress", takes_value: false },
+    OptionSpec { short: None, long: "watch", takes_value: false },
+    OptionSpec { short: None, long: "mmap", takes_value: false },
+    OptionSpec { short: None, long: "no-mmap", takes_value: false },
+    OptionSpec { short: Some('j'), long: "threads", takes_value: true },
+    OptionSpec { short: None, long: "color", takes_value: true },
+    OptionSpec { short: None, long: "no-messages", takes_value: false },
+    OptionSpec { short: None, long: "index", takes_value: false },
+    OptionSpec { short: Some('h'), long: "help", takes_value: false },
+    OptionSpec { short: Some('V'), long: "version", takes_value: false },
+];
+
+fn find_option<P: Fn(&OptionSpec) -> bool>(predicate: P) -> Option<&'static OptionSpec> {
+    OPTIONS.iter().find(|spec| predicate(spec))
+}
+
+fn required_value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("option '{}' requires a value", option))
+}
+
+fn apply(spec: &OptionSpec, value: Option<String>, state: &mut State) -> Result<Option<Command>, String> {
+    let value = value.unwrap_or_default();
+    let State { config, case, patterns, .. } = state;
+
+    match spec.long {
+        "ignore-case" => *case = Some(CaseMode::Insensitive),
+        "case-sensitive" => *case = Some(CaseMode::Sensitive),
+        "smart-case" => *case = Some(CaseMode::Smart),
+        "regex" => config.regex = true,
+        "fixed-strings" => config.regex = false,
+        "word-regexp" => config.word = true,
+        "line-regexp" => config.line = true,
+        "multiline" => config.multiline = true,
+        "fuzzy" => config.fuzzy = Some(number(spec, &value)?),
+        "sort-by-distance" => config.sort_by_distance = true,
+        "regexp" => patterns.get_or_insert_with(Vec::new).push(value),
+        "file" => {
+            let contents = fs::read_to_string(&value)
+                .map_err(|err| format!("couldn't read pattern file '{}': {}", value, err))?;
+            patterns.get_or_insert_with(Vec::new)
+                .extend(contents.lines().map(String::from));
+        }
+        "line-number" => config.line_number = true,
+        "after-context" => config.after_context = number(spec, &value)?,
+        "before-context" => config.before_context = number(spec, &value)?,
+        "context" => {
+            config.before_context = number(spec, &value)?;
+            config.after_context = config.before_context;
+        }
+        "invert-match" => config.invert_match = true,
+        "count" => config.output = OutputMode::Count,
+        "files-with-matches" => config.output = OutputMode::FilesWithMatches,
+        "files-without-match" => config.output = OutputMode::FilesWithoutMatches,
+        "quiet" => config.output = OutputMode::Quiet,
+        "json" => config.output = OutputMode::Json,
+        "max-count" => config.max_count = Some(number(spec, &value)? as u64),
+        "max-columns" => config.max_columns = Some(number(spec, &value)?),
+        "max-filesize" => config.walk.max_filesize = Some(size(spec, &value)?),
+        "replace" => config.replacement = Some(value),
+        "in-place" => config.in_place = true,
+        "backup" => config.backup_suffix = Some(value),
+        "glob" => config.walk.globs.push(value),
+        "hidden" => config.walk.hidden = true,
+        "no-ignore" => config.walk.no_ignore = true,
+        "text" => config.binary = BinaryMode::Text,
+        "binary" => {
+            config.binary = match value.as_str() {
+                "skip" => BinaryMode::Skip,
+                "text" => BinaryMode::Text,
+                "matches" => BinaryMode::Matches,
+                _ => return Err(format!("option '--binary' expects skip, text or matches, got '{}'", value)),
+            }
+        }
+        "encoding" => {
+            config.input.encoding = Some(
+                input::encoding_for_label(&value)
+                    .ok_or_else(|| format!("unknown encoding '{}'", value))?,
+            )
+        }
+        "decompress" => config.input.decompress = Decompress::Always,
+        "no-decompress" => config.input.decompress = Decompress::Never,
+        "watch" => config.watch = true,
+        "mmap" => config.input.strategy = ReadStrategy::Mmap,
+        "no-mmap" => config.input.strategy = ReadStrategy::Buffered,
+        "threads" => {
+            config.threads = match number(spec, &value)? {
+                0 => thread::available_parallelism().map_or(1, |n| n.get()),
+                n => n,
+            }
+        }
+        "color" => {
+            config.color = match value.as_str() {
+                "auto" => ColorChoice::Auto,
+                "always" => ColorChoice::Always,
+                "never" => ColorChoice::Never,
+                _ => return Err(format!("option '--color' expects auto, always or never, got '{}'", value)),
+            }
+        }
+        "no-messages" => config.no_messages = true,
+        "index" => config.index = true,
+        "help" => return Ok(Some(Command::Help)),
+        "version" => return Ok(Some(Command::Version)),
+        _ => unreachable!("option '--{}' has no handler", spec.long),
+    }
+
+    Ok(None)
+}
+
+fn number(spec: &OptionSpec, value: &str) -> Result<usize, String> {
+    value.parse()
+        .map_err(|_| format!("option '--{}' expects a non-negative number, got '{}'", spec.long, value))
+}
+
+fn size(spec: &OptionSpec, value: &str) -> Result<u64, String> {
+    parse_size(value)
+        .ok_or_else(|| format!("option '--{}' expects a size such as 512K or 10M, got '{}'", spec.long, value))
+}
+
+/// Parses a byte count with an optional binary `K`, `M` or `G` suffix, as
+/// `--max-filesize` takes it.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+
+    digits.parse::<u64>().ok().and_then(|n| n.checked_mul(multiplier))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    fn parse_args(args: &[&str], env_case_insensitive: bool) -> Result<Config, String> {
+        let env = defaults::from_env(|name| (env_case_insensitive && name == "CASE_INSENSITIVE").then(String::new));
+        parse_with(args, &[env])
+    }
+
+    fn parse_with(args: &[&str], defaults: &[Defaults]) -> Result<Config, String> {
+        let args = std::iter::once("minigrep").chain(args.iter().copied()).map(String::from);
+
+        match parse_with_defaults(args, defaults)? {
+            Command::Search(config) => Ok(*config),
+            _ => Err(String::from("not a search")),
+        }
+    }
+
+    #[test]
+    fn positional_arguments() {
+        let config = parse_args(&["frog", "poem.txt", "src"], false).unwrap();
+
+        assert_eq!(vec!["frog"], config.patterns);
+        assert_eq!(vec!["poem.txt", "src"], config.filenames);
+        assert_eq!(CaseMode::Sensitive, config.case);
+    }
+
+    #[test]
+    fn combined_short_flags() {
+        let config = parse_args(&["-inA2", "frog"], false).unwrap();
+
+        assert_eq!(CaseMode::Insensitive, config.case);
+        assert!(config.line_number);
+        assert_eq!(2, config.after_context);
+        assert_eq!(vec!["-"], config.filenames);
+    }
+
+    #[test]
+    fn long_options_with_values() {
+        let config = parse_args(&["--context=3", "--before-context", "1", "frog"], false).unwrap();
+
+        assert_eq!(1, config.before_context);
+        assert_eq!(3, config.after_context);
+    }
+
+    #[test]
+    fn terminator_allows_dash_queries() {
+        let config = parse_args(&["-n", "--", "-i", "-"], false).unwrap();
+
+        assert_eq!(vec!["-i"], config.patterns);
+        assert_eq!(vec!["-"], config.filenames);
+        assert_eq!(CaseMode::Sensitive, config.case);
+    }
+
+    #[test]
+    fn repeated_patterns() {
+        let path = std::env::temp_dir().join(format!("minigrep-patterns-{}", std::process::id()));
+        fs::write(&path, "Config\nrun\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let config = parse_args(&["-e", "frog", "-f", &path, "-etoad", "poem.txt"], false);
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(vec!["frog", "Config", "run", "toad"], config.patterns);
+        assert_eq!(vec!["poem.txt"], config.filenames);
+    }
+
+    #[test]
+    fn missing_pattern_file() {
+        let err = parse_args(&["-f", "no/such/patterns", "poem.txt"], false).unwrap_err();
+
+        assert!(err.starts_with("couldn't read pattern file 'no/such/patterns'"));
+    }
+
+    #[test]
+    fn output_modes() {
+        let config = parse_args(&["-vc", "frog"], false).unwrap();
+        assert!(config.invert_match);
+        assert_eq!(OutputMode::Count, config.output);
+
+        assert_eq!(OutputMode::Quiet, parse_args(&["-lq", "frog"], false).unwrap().output);
+    }
+
+    #[test]
+    fn limits() {
+        let config = parse_args(&["-m2", "--max-columns=80", "--max-filesize", "10M", "frog"], false).unwrap();
+
+        assert_eq!(Some(2), config.max_count);
+        assert_eq!(Some(80), config.max_columns);
+        assert_eq!(Some(10 << 20), config.walk.max_filesize);
+        assert_eq!(None, parse_args(&["frog"], false).unwrap().max_count);
+        assert_eq!(Some(512), parse_args(&["--max-filesize=512", "frog"], false).unwrap().walk.max_filesize);
+        assert!(parse_args(&["--max-filesize=10T", "frog"], false).is_err());
+    }
+
+    #[test]
+    fn matching_options() {
+        let config = parse_args(&["--regex", "-wxU", "-F", "frog"], false).unwrap();
+
+        assert!(!config.regex);
+        assert!(config.word);
+        assert!(config.line);
+        assert!(config.multiline);
+    }
+
+    #[test]
+    fn replace_options() {
+        let config = parse_args(&["-r", "toad", "--in-place", "--backup=.orig", "frog", "poem.txt"], false).unwrap();
+
+        assert_eq!(Some(String::from("toad")), config.replacement);
+        assert!(config.in_place);
+        assert_eq!(Some(String::from(".orig")), config.backup_suffix);
+
+        assert!(parse_args(&["--in-place", "frog"], false).is_err());
+        assert!(parse_args(&["-v", "-r", "toad", "--in-place", "frog"], false).is_err());
+        assert!(parse_args(&["-r", "toad", "--backup=.orig", "frog"], false).is_err());
+        assert!(parse_args(&["-U", "-r", "toad", "--in-place", "frog"], false).is_err());
+        assert!(parse_args(&["-E", "latin1", "-r", "toad", "--in-place", "frog"], false).is_err());
+        for mode in ["-q", "-l", "-L", "-c"] {
+            assert!(parse_args(&[mode, "-r", "toad", "--in-place", "frog"], false).is_err());
+        }
+    }
+
+    #[test]
+    fn fuzzy_options() {
+        let config = parse_args(&["--fuzzy", "2", "--sort-by-distance", "recieve", "mail.log"], false).unwrap();
+
+        assert_eq!(Some(2), config.fuzzy);
+        assert!(config.sort_by_distance);
+
+        assert!(parse_args(&["--fuzzy=two", "recieve"], false).is_err());
+        assert!(parse_args(&["--fuzzy=1", "--regex", "recieve"], false).is_err());
+        assert!(parse_args(&["--sort-by-distance", "recieve"], false).is_err());
+        assert!(parse_args(&["--fuzzy=1", "--sort-by-distance", "-C", "1", "recieve"], false).is_err());
+    }
+
+    #[test]
+    fn watch_options() {
+        assert!(parse_args(&["--watch", "-i", "error", "app.log"], false).unwrap().watch);
+
+        assert!(parse_args(&["--watch", "error"], false).is_err());
+        assert!(parse_args(&["--watch", "-c", "error", "app.log"], false).is_err());
+        assert!(parse_args(&["--watch", "--index", "error", "logs"], false).is_err());
+    }
+
+    #[test]
+    fn walk_options() {
+        let config = parse_args(&["-g", "*.rs", "--glob=!target/**", "--hidden", "-a", "fn", "."], false).unwrap();
+
+        assert_eq!(vec!["*.rs", "!target/**"], config.walk.globs);
+        assert!(config.walk.hidden);
+        assert!(!config.walk.no_ignore);
+        assert_eq!(BinaryMode::Text, config.binary);
+        assert_eq!(Decompress::Auto, config.input.decompress);
+        assert_eq!(Decompress::Always, parse_args(&["-z", "fn"], false).unwrap().input.decompress);
+        assert_eq!(ReadStrategy::Auto, config.input.strategy);
+        assert_eq!(ReadStrategy::Buffered, parse_args(&["--no-mmap", "fn"], false).unwrap().input.strategy);
+    }
+
+    #[test]
+    fn binary_and_encoding() {
+        let config = parse_args(&["--binary=matches", "--encoding", "Shift_JIS", "frog"], false).unwrap();
+
+        assert_eq!(BinaryMode::Matches, config.binary);
+        assert_eq!(Some(encoding_rs::SHIFT_JIS), config.input.encoding);
+        assert_eq!(BinaryMode::Skip, parse_args(&["frog"], false).unwrap().binary);
+        assert!(parse_args(&["-E", "klingon", "frog"], false).is_err());
+    }
+
+    #[test]
+    fn threads() {
+        assert_eq!(1, parse_args(&["frog"], false).unwrap().threads);
+        assert_eq!(4, parse_args(&["--threads", "4", "frog"], false).unwrap().threads);
+        assert!(parse_args(&["-j0", "frog"], false).unwrap().threads >= 1);
+    }
+
+    #[test]
+    fn color_choice() {
+        assert_eq!(ColorChoice::Auto, parse_args(&["frog"], false).unwrap().color);
+        assert_eq!(ColorChoice::Never, parse_args(&["--color=never", "frog"], false).unwrap().color);
+        assert_eq!(ColorChoice::Always, parse_args(&["--color", "always", "frog"], false).unwrap().color);
+        assert!(parse_args(&["--color=sometimes", "frog"], false).is_err());
+    }
+
+    #[test]
+    fn flags_override_environment() {
+        assert_eq!(CaseMode::Insensitive, parse_args(&["frog"], true).unwrap().case);
+        assert_eq!(CaseMode::Sensitive, parse_args(&["-s", "frog"], true).unwrap().case);
+        assert_eq!(CaseMode::Smart, parse_args(&["-S", "frog"], true).unwrap().case);
+    }
+
+    #[test]
+    fn defaults_precedence() {
+        let file = Defaults {
+            source: String::from("config file 'config.toml'"),
+            args: vec![String::from("--color=always"), String::from("-S"), String::from("--glob=!*.min.js")],
+        };
+        let env = defaults::from_env(|name| (name == "MINIGREP_COLOR").then(|| String::from("never")));
+        let defaults = [file, env];
+
+        let config = parse_with(&["frog"], &defaults).unwrap();
+        assert_eq!(ColorChoice::Never, config.color);
+        assert_eq!(CaseMode::Smart, config.case);
+
+        let config = parse_with(&["--color=auto", "-s", "-g", "*.rs", "frog"], &defaults).unwrap();
+        assert_eq!(ColorChoice::Auto, config.color);
+        assert_eq!(CaseMode::Sensitive, config.case);
+        assert_eq!(vec!["!*.min.js", "*.rs"], config.walk.globs);
+    }
+
+    #[test]
+    fn default_errors_name_their_source() {
+        let file = Defaults { source: String::from("config file 'config.toml'"), args: vec![String::from("--bogus")] };
+        assert_eq!(
+            Err(String::from("config file 'config.toml': unknown option '--bogus'")),
+            parse_with(&["frog"], &[file]).map(|_| ())
+        );
+
+        let file = Defaults { source: String::from("config file 'config.toml'"), args: vec![String::from("frog")] };
+        assert!(parse_with(&["toad"], &[file]).is_err());
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(Err(String::from("unknown option '-Z'")), parse_args(&["-Z", "frog"], false).map(|_| ()));
+        assert_eq!(
+            Err(String::from("option '-A' requires a value")),
+            parse_args(&["frog", "-A"], false).map(|_| ())
+        );
+        assert_eq!(
+            Err(String::from("option '--context' expects a non-negative number, got 'x'")),
+            parse_args(&["--context=x", "frog"], false).map(|_| ())
+        );
+        assert_eq!(
+            Err(String::from("option '--regex' doesn't take a value")),
+            parse_args(&["--regex=1", "frog"], false).map(|_| ())
+        );
+        assert!(parse_args(&[], false).is_err());
+    }
+
+    #[test]
+    fn index_subcommand() {
+        let parse = |args: &[&str]| {
+            let args = std::iter::once("minigrep").chain(args.iter().copied()).map(String::from);
+            parse_with_defaults(args, &[])
+        };
+
+        assert!(matches!(
+            parse(&["index", "build"]),
+            Ok(Command::Index { root, update: false }) if root == Path::new(".")
+        ));
+        assert!(matches!(
+            parse(&["index", "update", "src"]),
+            Ok(Command::Index { root, update: true }) if root == Path::new("src")
+        ));
+        assert_eq!(vec!["index"], parse_args(&["index", "poem.txt"], false).unwrap().patterns);
+        assert_eq!(vec!["build"], parse_args(&["--", "index", "build"], false).unwrap().filenames);
+        assert!(parse_args(&["--index", "frog", "src"], false).unwrap().index);
+    }
+
+    #[test]
+    fn help_and_version() {
+        let args = ["minigrep", "-n", "--help", "--bogus"].iter().map(|s| s.to_string());
+        assert!(matches!(parse_with_defaults(args, &[]), Ok(Command::Help)));
+
+        let args = ["minigrep", "-V"].iter().map(|s| s.to_string());
+        assert!(matches!(parse_with_defaults(args, &[]), Ok(Command::Version)));
+    }
+}
@@ -0,0 +1,104 @@
+use std::ops::Range;
+
+/// Text after Unicode case folding, remembering where each folded byte came
+/// from so match positions can be mapped back to the original text.
+///
+/// Folding can change the length of a character (`ß` folds to `ss` and `İ`
+/// to `i̇`), so offsets found in the folded text are not valid in the
+/// original one without this mapping.
+pub struct Folded {
+    pub text: String,
+    origins: Vec<usize>,
+}
+
+impl Folded {
+    pub fn new(original: &str) -> Folded {
+        let mut text = String::with_capacity(original.len());
+        let mut origins = Vec::with_capacity(original.len());
+
+        for (offset, c) in original.char_indices() {
+            let before = text.len();
+            fold_char(c, &mut text);
+            origins.resize(origins.len() + text.len() - before, offset);
+        }
+
+        Folded { text, origins }
+    }
+
+    /// Maps a byte offset at a character boundary of the original text to
+    /// the offset in the folded text where that character's folding starts.
+    pub fn folded_offset(&self, offset: usize) -> usize {
+        self.origins.partition_point(|&origin| origin < offset)
+    }
+
+    /// Maps a byte range of the folded text to the smallest range of whole
+    /// characters of `original` that produced it.
+    pub fn original_range(&self, original: &str, range: Range<usize>) -> Range<usize> {
+        let start = self.origins.get(range.start).copied().unwrap_or(original.len());
+
+        if range.is_empty() {
+            return start..start;
+        }
+
+        let last = self.origins[range.end - 1];
+        let end = last + original[last..].chars().next().map_or(0, char::len_utf8);
+        start..end
+    }
+}
+
+/// Returns the case-folded form of `s`.
+pub fn fold(s: &str) -> String {
+    let mut folded = String::with_capacity(s.len());
+    for c in s.chars() {
+        fold_char(c, &mut folded);
+    }
+    folded
+}
+
+fn fold_char(c: char, out: &mut String) {
+    if c.is_ascii() {
+        out.push(c.to_ascii_lowercase());
+    } else if c == 'ı' {
+        // Dotless i has no folding; only Turkish tailoring relates it to I.
+        out.push(c);
+    } else if c == 'ẞ' {
+        // Capital sharp s lowercases to ß, which folds further to ss.
+        out.push_str("ss");
+    } else {
+        // Going through the uppercase form folds characters that have no
+        // single lowercase counterpart: ß -> SS -> ss, ς -> Σ -> σ, ſ -> S -> s.
+        for upper in c.to_uppercase() {
+            out.extend(upper.to_lowercase());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn length_changing_folds() {
+        assert_eq!("strasse", fold("STRAßE"));
+        assert_eq!("strasse", fold("STRAẞE"));
+        assert_eq!("σίσυφοσ", fold("ΣΊΣΥΦΟΣ"));
+        assert_eq!(fold("σίσυφος"), fold("ΣΊΣΥΦΟΣ"));
+        assert_eq!("i\u{307}stanbul", fold("İstanbul"));
+        assert_eq!("ı", fold("ı"));
+    }
+
+    #[test]
+    fn maps_ranges_back() {
+        let original = "Die Straße";
+        let folded = Folded::new(original);
+
+        // "sse" in the folded text covers "ße" in the original.
+        let start = folded.text.find("sse").unwrap();
+        assert_eq!(8..11, folded.original_range(original, start..start + 3));
+        // Half of the expansion of ß still maps to the whole character.
+        assert_eq!(8..10, folded.original_range(original, start..start + 1));
+        assert_eq!(11..11, folded.original_range(original, folded.text.len()..folded.text.len()));
+        assert_eq!(start, folded.folded_offset(8));
+        assert_eq!(folded.text.len(), folded.folded_offset(original.len()));
+    }
+}
@@ -0,0 +1,188 @@
+//! Default options from the config file and `MINIGREP_*` environment
+//! variables.
+//!
+//! Defaults are turned into command-line options that are parsed before the
+//! real command line, so later sources override earlier ones. From lowest to
+//! highest precedence:
+//!
+//! 1. built-in defaults,
+//! 2. the config file,
+//! 3. environment variables,
+//! 4. options on the command line.
+//!
+//! Globs add up instead: those of every source are used.
+//!
+//! The config file is `$MINIGREP_CONFIG` if set (an empty value disables
+//! it), otherwise `$XDG_CONFIG_HOME/minigrep/config.toml`, falling back to
+//! `~/.config/minigrep/config.toml`. It's fine for it not to exist. All keys
+//! are optional:
+//!
+//! ```toml
+//! # Options added to every search.
+//! args = ["--smart-case", "--line-number"]
+//! # Same as --glob for each entry.
+//! globs = ["!*.min.js", "!vendor/"]
+//! # auto, always or never, like --color.
+//! color = "always"
+//! ```
+//!
+//! The environment variables are `MINIGREP_COLOR` (like `--color`),
+//! `MINIGREP_THREADS` (like `--threads`) and `CASE_INSENSITIVE`, which acts
+//! like `-i` when set to anything.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+/// Options taken from one source of defaults.
+#[derive(Debug, PartialEq)]
+pub struct Defaults {
+    /// Where the options came from, for error messages.
+    pub source: String,
+    pub args: Vec<String>,
+}
+
+/// Collects the defaults of the config file and the environment, in order
+/// of increasing precedence.
+pub fn load() -> Result<Vec<Defaults>, String> {
+    let mut defaults = Vec::new();
+
+    if let Some((path, required)) = config_path(|name| env::var_os(name).map(PathBuf::from)) {
+        match fs::read_to_string(&path) {
+            Ok(contents) => defaults.push(from_file(&path, &contents)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => {}
+            Err(err) => return Err(format!("couldn't read config file '{}': {}", path.display(), err)),
+        }
+    }
+
+    defaults.push(from_env(|name| env::var(name).ok()));
+    Ok(defaults)
+}
+
+/// Returns the config file to read and whether it must exist, which it
+/// must when named by `MINIGREP_CONFIG`.
+fn config_path<F: Fn(&str) -> Option<PathBuf>>(var: F) -> Option<(PathBuf, bool)> {
+    if let Some(path) = var("MINIGREP_CONFIG") {
+        return if path.as_os_str().is_empty() { None } else { Some((path, true)) };
+    }
+
+    let config_home = var("XDG_CONFIG_HOME")
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| var("HOME").map(|home| home.join(".config")))?;
+    Some((config_home.join("minigrep").join("config.toml"), false))
+}
+
+/// Parses the config file at `path` with the given contents.
+pub fn from_file(path: &Path, contents: &str) -> Result<Defaults, String> {
+    let source = format!("config file '{}'", path.display());
+    let table: Table = contents.parse().map_err(|err| format!("{}: {}", source, err))?;
+    let mut args = Vec::new();
+
+    for (key, value) in &table {
+        match (key.as_str(), value) {
+            ("args", Value::Array(values)) => args.extend(strings(&source, key, values)?),
+            ("globs", Value::Array(values)) => {
+                for glob in strings(&source, key, values)? {
+                    args.push(format!("--glob={}", glob));
+                }
+            }
+            ("color", Value::String(color)) => args.push(format!("--color={}", color)),
+            ("args" | "globs", _) => return Err(format!("{}: '{}' must be an array of strings", source, key)),
+            ("color", _) => return Err(format!("{}: 'color' must be a string", source)),
+            _ => return Err(format!("{}: unknown key '{}'", source, key)),
+        }
+    }
+
+    Ok(Defaults { source, args })
+}
+
+fn strings(source: &str, key: &str, values: &[Value]) -> Result<Vec<String>, String> {
+    values.iter()
+        .map(|value| {
+            value.as_str()
+                .map(String::from)
+                .ok_or_else(|| format!("{}: '{}' must be an array of strings", source, key))
+        })
+        .collect()
+}
+
+/// Reads the options set by environment variables.
+pub fn from_env<F: Fn(&str) -> Option<String>>(var: F) -> Defaults {
+    let mut args = Vec::new();
+
+    if var("CASE_INSENSITIVE").is_some() {
+        args.push(String::from("--ignore-case"));
+    }
+    if let Some(color) = var("MINIGREP_COLOR") {
+        args.push(format!("--color={}", color));
+    }
+    if let Some(threads) = var("MINIGREP_THREADS") {
+        args.push(format!("--threads={}", threads));
+    }
+
+    Defaults { source: String::from("environment"), args }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_options() {
+        let contents = r#"
+            args = ["--smart-case", "-n"]
+            globs = ["!*.min.js"]
+            color = "never"
+        "#;
+
+        let defaults = from_file(Path::new("config.toml"), contents).unwrap();
+
+        assert_eq!(vec!["--smart-case", "-n", "--color=never", "--glob=!*.min.js"], defaults.args);
+    }
+
+    #[test]
+    fn file_errors() {
+        let path = Path::new("config.toml");
+
+        assert_eq!(
+            Err(String::from("config file 'config.toml': unknown key 'colour'")),
+            from_file(path, "colour = \"never\"")
+        );
+        assert!(from_file(path, "args = \"-n\"").is_err());
+        assert!(from_file(path, "args = [1]").is_err());
+        assert!(from_file(path, "args = [").is_err());
+    }
+
+    #[test]
+    fn environment_options() {
+        let var = |name: &str| match name {
+            "CASE_INSENSITIVE" => Some(String::new()),
+            "MINIGREP_THREADS" => Some(String::from("4")),
+            _ => None,
+        };
+
+        assert_eq!(vec!["--ignore-case", "--threads=4"], from_env(var).args);
+    }
+
+    #[test]
+    fn config_file_location() {
+        let var = |name: &str| match name {
+            "XDG_CONFIG_HOME" => Some(PathBuf::from("/xdg")),
+            "HOME" => Some(PathBuf::from("/home/ferris")),
+            _ => None,
+        };
+        assert_eq!(Some((PathBuf::from("/xdg/minigrep/config.toml"), false)), config_path(var));
+
+        let var = |name: &str| (name == "HOME").then(|| PathBuf::from("/home/ferris"));
+        assert_eq!(
+            Some((PathBuf::from("/home/ferris/.config/minigrep/config.toml"), false)),
+            config_path(var)
+        );
+
+        let var = |name: &str| (name == "MINIGREP_CONFIG").then(PathBuf::new);
+        assert_eq!(None, config_path(var));
+    }
+}
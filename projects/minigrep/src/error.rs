@@ -0,0 +1,94 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything that can go wrong while searching.
+///
+/// Errors about a single file (`Io`, `Decode` and `Walk`) are reported and
+/// the search goes on with the next file; the others stop it.
+#[derive(Debug)]
+pub enum MinigrepError {
+    /// The command line or a default from the config file is invalid.
+    Usage(String),
+    /// A file couldn't be opened or read.
+    Io { path: PathBuf, source: io::Error },
+    /// A file couldn't be decompressed or decoded.
+    Decode { path: PathBuf, source: io::Error },
+    /// A pattern isn't valid.
+    Pattern(String),
+    /// A directory couldn't be walked.
+    Walk(ignore::Error),
+    /// Writing the results failed, including errors returned by a `Sink`.
+    Output(io::Error),
+}
+
+impl MinigrepError {
+    /// Classifies an error that happened while opening or reading `path`.
+    pub fn read(path: &Path, source: io::Error) -> MinigrepError {
+        let path = path.to_path_buf();
+
+        if source.kind() == io::ErrorKind::InvalidData {
+            MinigrepError::Decode { path, source }
+        } else {
+            MinigrepError::Io { path, source }
+        }
+    }
+
+    /// Whether the error only concerns one file, so the search can go on.
+    pub fn is_per_file(&self) -> bool {
+        matches!(self, MinigrepError::Io { .. } | MinigrepError::Decode { .. } | MinigrepError::Walk(_))
+    }
+}
+
+impl fmt::Display for MinigrepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MinigrepError::Usage(message) => write!(f, "{}", message),
+            MinigrepError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            MinigrepError::Decode { path, source } => write!(f, "{}: couldn't decode: {}", path.display(), source),
+            MinigrepError::Pattern(message) => write!(f, "invalid pattern: {}", message),
+            MinigrepError::Walk(err) => write!(f, "{}", err),
+            MinigrepError::Output(err) => write!(f, "couldn't write output: {}", err),
+        }
+    }
+}
+
+impl Error for MinigrepError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MinigrepError::Io { source, .. } | MinigrepError::Decode { source, .. } => Some(source),
+            MinigrepError::Walk(err) => Some(err),
+            MinigrepError::Output(err) => Some(err),
+            MinigrepError::Usage(_) | MinigrepError::Pattern(_) => None,
+        }
+    }
+}
+
+impl From<regex::Error> for MinigrepError {
+    fn from(err: regex::Error) -> MinigrepError {
+        MinigrepError::Pattern(err.to_string())
+    }
+}
+
+impl From<aho_corasick::BuildError> for MinigrepError {
+    fn from(err: aho_corasick::BuildError) -> MinigrepError {
+        MinigrepError::Pattern(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_errors() {
+        let not_found = MinigrepError::read(Path::new("a.txt"), io::Error::from(io::ErrorKind::NotFound));
+        let invalid = MinigrepError::read(Path::new("a.gz"), io::Error::new(io::ErrorKind::InvalidData, "corrupt"));
+
+        assert!(matches!(not_found, MinigrepError::Io { .. }));
+        assert_eq!("a.gz: couldn't decode: corrupt", invalid.to_string());
+        assert!(invalid.is_per_file());
+        assert!(!MinigrepError::Pattern(String::from("unclosed group")).is_per_file());
+    }
+}
@@ -0,0 +1,335 @@
+//! Approximate matching (`--fuzzy K`): a line matches when some part of it
+//! is within Levenshtein distance K of a pattern, counting inserted, deleted
+//! and substituted characters.
+//!
+//! Distances are computed a text character at a time with Myers'
+//! bit-parallel algorithm, which keeps a whole column of the edit-distance
+//! table in two machine words. Patterns longer than 64 characters don't fit
+//! and use the plain dynamic-programming table instead.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::casefold::{self, Folded};
+use crate::matcher::{Finder, Matcher};
+
+/// Matches the patterns within `max_distance` edits. Case-insensitive
+/// matching compares Unicode case-folded text.
+pub struct Fuzzy {
+    patterns: Vec<Pattern>,
+    max_distance: usize,
+    case_sensitive: bool,
+}
+
+impl Fuzzy {
+    pub fn new<S: AsRef<str>>(patterns: &[S], max_distance: usize, case_sensitive: bool) -> Fuzzy {
+        let patterns = patterns.iter()
+            .map(|pattern| {
+                if case_sensitive {
+                    Pattern::new(pattern.as_ref())
+                } else {
+                    Pattern::new(&casefold::fold(pattern.as_ref()))
+                }
+            })
+            .collect();
+
+        Fuzzy { patterns, max_distance, case_sensitive }
+    }
+
+    /// Returns the byte range of the first match starting at or after the
+    /// byte offset `start` of a text of `len` bytes, given its characters.
+    fn find_in(&self, chars: &[(usize, char)], len: usize, start: usize) -> Option<Range<usize>> {
+        let first = chars.partition_point(|&(offset, _)| offset < start);
+        let chars = &chars[first..];
+        let byte = |i: usize| chars.get(i).map_or(len, |&(offset, _)| offset);
+
+        self.patterns.iter()
+            .filter_map(|pattern| pattern.find(chars, self.max_distance))
+            .min_by_key(|(range, distance)| (range.start, *distance, usize::MAX - range.end))
+            .map(|(range, _)| byte(range.start)..byte(range.end))
+    }
+}
+
+impl Matcher for Fuzzy {
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        self.finder(line)(start)
+    }
+
+    fn finder<'t>(&'t self, text: &'t str) -> Finder<'t> {
+        if self.case_sensitive {
+            let chars: Vec<(usize, char)> = text.char_indices().collect();
+            return Box::new(move |start| self.find_in(&chars, text.len(), start));
+        }
+
+        let folded = Folded::new(text);
+        let chars: Vec<(usize, char)> = folded.text.char_indices().collect();
+        Box::new(move |start| {
+            let range = self.find_in(&chars, folded.text.len(), folded.folded_offset(start))?;
+            Some(folded.original_range(text, range))
+        })
+    }
+
+    fn distance(&self, line: &str) -> Option<usize> {
+        let folded;
+        let text = if self.case_sensitive {
+            line
+        } else {
+            folded = casefold::fold(line);
+            &folded
+        };
+        let chars: Vec<char> = text.chars().collect();
+
+        self.patterns.iter()
+            .map(|pattern| pattern.best_distance(&chars))
+            .filter(|&distance| distance <= self.max_distance)
+            .min()
+    }
+}
+
+/// A pattern and, for each of its characters, the bit mask of the positions
+/// where it occurs, forwards and reversed.
+struct Pattern {
+    chars: Vec<char>,
+    forward: Masks,
+    backward: Masks,
+}
+
+impl Pattern {
+    fn new(pattern: &str) -> Pattern {
+        let chars: Vec<char> = pattern.chars().collect();
+        let reversed: Vec<char> = chars.iter().rev().copied().collect();
+
+        Pattern { forward: Masks::new(&chars), backward: Masks::new(&reversed), chars }
+    }
+
+    /// Finds the match ending first in `text`, as character indexes, and its
+    /// distance.
+    ///
+    /// Once a match ends, it's extended while that lowers the distance, and
+    /// its start is found by matching the reversed pattern backwards from
+    /// there, keeping the shortest span with that distance. Matching forwards
+    /// again from the start then settles the end on the longest span with the
+    /// best distance, so `recieve` is matched whole rather than as `recie`.
+    fn find(&self, text: &[(usize, char)], max_distance: usize) -> Option<(Range<usize>, usize)> {
+        let m = self.chars.len();
+        if m <= max_distance {
+            return Some((0..0, m));
+        }
+
+        let mut scanner = Scanner::new(&self.chars, &self.forward, false);
+        let mut found: Option<(usize, usize)> = None;
+
+        for (i, &(_, c)) in text.iter().enumerate() {
+            let distance = scanner.step(c);
+            match found {
+                Some((_, best)) if distance < best => found = Some((i + 1, distance)),
+                Some(_) => break,
+                None if distance <= max_distance => found = Some((i + 1, distance)),
+                None => {}
+            }
+        }
+        let (end, distance) = found?;
+
+        let reversed: Vec<char> = self.chars.iter().rev().copied().collect();
+        let mut scanner = Scanner::new(&reversed, &self.backward, true);
+        let mut start = end;
+        for (n, &(_, c)) in text[..end].iter().rev().take(m + max_distance).enumerate() {
+            if scanner.step(c) == distance {
+                start = end - n - 1;
+                break;
+            }
+        }
+
+        let mut scanner = Scanner::new(&self.chars, &self.forward, true);
+        let mut best = (end, distance);
+        for (n, &(_, c)) in text[start..].iter().take(m + max_distance).enumerate() {
+            let distance = scanner.step(c);
+            if distance <= best.1 {
+                best = (start + n + 1, distance);
+            }
+        }
+
+        Some((start..best.0, best.1))
+    }
+
+    /// Returns the smallest distance of the pattern to any part of `text`.
+    fn best_distance(&self, text: &[char]) -> usize {
+        let mut scanner = Scanner::new(&self.chars, &self.forward, false);
+        text.iter().map(|&c| scanner.step(c)).fold(self.chars.len(), usize::min)
+    }
+}
+
+/// Bit masks of where each character occurs in a pattern of at most 64
+/// characters; longer patterns don't use them.
+struct Masks {
+    ascii: [u64; 128],
+    other: HashMap<char, u64>,
+}
+
+impl Masks {
+    fn new(chars: &[char]) -> Masks {
+        let mut masks = Masks { ascii: [0; 128], other: HashMap::new() };
+
+        if chars.len() <= 64 {
+            for (i, &c) in chars.iter().enumerate() {
+                if c.is_ascii() {
+                    masks.ascii[c as usize] |= 1 << i;
+                } else {
+                    *masks.other.entry(c).or_default() |= 1 << i;
+                }
+            }
+        }
+        masks
+    }
+
+    fn get(&self, c: char) -> u64 {
+        if c.is_ascii() {
+            self.ascii[c as usize]
+        } else {
+            self.other.get(&c).copied().unwrap_or(0)
+        }
+    }
+}
+
+/// The last column of the edit-distance table between a pattern and the
+/// text read so far.
+///
+/// Unanchored, a match may start anywhere in the text and `step` returns
+/// the distance of the best match ending at the character read. Anchored,
+/// it must start at the first character read.
+struct Scanner<'a> {
+    masks: &'a Masks,
+    state: State<'a>,
+}
+
+enum State<'a> {
+    Bits { pv: u64, mv: u64, last: u64, carry: u64, distance: usize },
+    Table { chars: &'a [char], column: Vec<usize>, anchored: bool },
+}
+
+impl<'a> Scanner<'a> {
+    fn new(chars: &'a [char], masks: &'a Masks, anchored: bool) -> Scanner<'a> {
+        let m = chars.len();
+
+        let state = if (1..=64).contains(&m) {
+            State::Bits { pv: !0, mv: 0, last: 1 << (m - 1), carry: anchored as u64, distance: m }
+        } else {
+            State::Table { chars, column: (0..=m).collect(), anchored }
+        };
+
+        Scanner { masks, state }
+    }
+
+    fn step(&mut self, c: char) -> usize {
+        match &mut self.state {
+            State::Bits { pv, mv, last, carry, distance } => {
+                let eq = self.masks.get(c);
+                let xv = eq | *mv;
+                let xh = ((eq & *pv).wrapping_add(*pv) ^ *pv) | eq;
+                let mut ph = *mv | !(xh | *pv);
+                let mut mh = *pv & xh;
+
+                if ph & *last != 0 {
+                    *distance += 1;
+                } else if mh & *last != 0 {
+                    *distance -= 1;
+                }
+
+                ph = (ph << 1) | *carry;
+                mh <<= 1;
+                *pv = mh | !(xv | ph);
+                *mv = ph & xv;
+                *distance
+            }
+            State::Table { chars, column, anchored } => {
+                let mut diagonal = column[0];
+                column[0] = if *anchored { column[0] + 1 } else { 0 };
+
+                for (i, &p) in chars.iter().enumerate() {
+                    let substitution = diagonal + usize::from(p != c);
+                    diagonal = column[i + 1];
+                    column[i + 1] = substitution.min(column[i + 1] + 1).min(column[i] + 1);
+                }
+                column[chars.len()]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn edit_distance(a: &str, b: &str) -> usize {
+        let b: Vec<char> = b.chars().collect();
+        let mut column: Vec<usize> = (0..=b.len()).collect();
+
+        for (i, x) in a.chars().enumerate() {
+            let mut diagonal = column[0];
+            column[0] = i + 1;
+            for (j, &y) in b.iter().enumerate() {
+                let substitution = diagonal + usize::from(x != y);
+                diagonal = column[j + 1];
+                column[j + 1] = substitution.min(column[j + 1] + 1).min(column[j] + 1);
+            }
+        }
+        column[b.len()]
+    }
+
+    #[test]
+    fn finds_typos() {
+        let matcher = Fuzzy::new(&["receive"], 1, true);
+
+        assert_eq!(Some(8..14), matcher.find("did not recive it"));
+        assert_eq!(Some(1), matcher.distance("did not recive it"));
+        assert_eq!(Some(0), matcher.distance("receive"));
+        // Swapping two letters takes two edits.
+        assert!(!matcher.is_match("did not recieve it"));
+        assert!(Fuzzy::new(&["receive"], 2, true).is_match("did not recieve it"));
+        assert_eq!(None, matcher.distance("nothing here"));
+    }
+
+    #[test]
+    fn extends_to_the_best_end() {
+        let matcher = Fuzzy::new(&["frog"], 1, true);
+
+        assert_eq!(Some(2..6), matcher.find("a frog"));
+        assert_eq!(Some(2..5), matcher.find("a frgo"));
+        assert_eq!(Some(3..10), Fuzzy::new(&["receive"], 2, true).find("we recieve mail"));
+        assert_eq!(vec![0..4, 9..12], matcher.find_all("frog and fog"));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let matcher = Fuzzy::new(&["straße"], 1, false);
+
+        assert_eq!(Some(4..11), matcher.find("Die STRASSEN"));
+        assert!(!Fuzzy::new(&["straße"], 1, true).is_match("Die STRASSEN"));
+    }
+
+    #[test]
+    fn long_patterns() {
+        let pattern = "a rather long pattern that does not fit into a single machine word";
+        let line = format!("it said: {}!", pattern.replace("machine", "machne"));
+        let matcher = Fuzzy::new(&[pattern], 2, true);
+
+        assert_eq!(Some(9..line.len() - 1), matcher.find(&line));
+        assert_eq!(Some(1), matcher.distance(&line));
+    }
+
+    #[test]
+    fn agrees_with_edit_distance() {
+        let words = ["kitten", "sitting", "sittin", "kitchen", "mitten", "itten", "k"];
+
+        for pattern in words {
+            for text in words {
+                let best = (0..=text.len())
+                    .flat_map(|start| (start..=text.len()).map(move |end| &text[start..end]))
+                    .map(|part| edit_distance(pattern, part))
+                    .min();
+
+                assert_eq!(best, Fuzzy::new(&[pattern], 10, true).distance(text), "{} in {}", pattern, text);
+            }
+        }
+    }
+}
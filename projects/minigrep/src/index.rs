@@ -0,0 +1,491 @@
+//! A trigram index that narrows which files a search has to read.
+//!
+//! `minigrep index build DIR` records, for every file the search of `DIR`
+//! would visit, its size, modification time and the set of trigrams (runs
+//! of three bytes) in its case-folded contents. The index is written to
+//! `DIR/.minigrep-index`; `minigrep index update DIR` rewrites it, reading
+//! again only the files that are new or changed.
+//!
+//! Searching with `--index` skips the files whose trigrams show they can't
+//! match. A file is still searched when it isn't in the index or its size
+//! or modification time changed since, so a stale index makes the search
+//! slower but never wrong. Searches that can select a file without finding
+//! a literal in it (`-v`, `-L`, patterns shorter than three characters or
+//! regexes without a required prefix) don't use the index at all.
+//!
+//! The file format is a magic number followed by one record per file, with
+//! integers in little-endian order:
+//!
+//! ```text
+//! "MGIDX\0\0\x02"
+//! u32 number of files
+//! per file: u32 path length, path (UTF-8, relative to DIR),
+//!           u64 seconds and u32 nanoseconds of the modification time,
+//!           u64 size, u32 number of trigrams, u32 per trigram (sorted)
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use regex_syntax::hir::literal::Extractor;
+use regex_syntax::ParserBuilder;
+
+use crate::casefold;
+use crate::error::MinigrepError;
+use crate::input::{self, Decompress, InputOptions};
+use crate::printer::OutputMode;
+use crate::walk::{self, WalkOptions};
+use crate::Config;
+
+/// Name of the index file in the indexed directory.
+pub const INDEX_FILE: &str = ".minigrep-index";
+
+const MAGIC: &[u8; 8] = b"MGIDX\0\0\x02";
+
+/// The trigrams of every indexed file under a directory.
+pub struct Index {
+    root: PathBuf,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+struct Entry {
+    modified: (u64, u32),
+    size: u64,
+    trigrams: Vec<u32>,
+}
+
+/// What building or updating an index did.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BuildStats {
+    /// Number of files in the index.
+    pub files: u64,
+    /// Number of files that had to be read.
+    pub read: u64,
+}
+
+impl Index {
+    /// Returns where the index of `root` is stored.
+    pub fn path(root: &Path) -> PathBuf {
+        root.join(INDEX_FILE)
+    }
+
+    /// Loads the index of `root`.
+    pub fn load(root: &Path) -> Result<Index, MinigrepError> {
+        let path = Index::path(root);
+        let io_error = |source| MinigrepError::Io { path: path.clone(), source };
+
+        let file = File::open(&path).map_err(io_error)?;
+        let entries = read_entries(BufReader::new(file)).map_err(io_error)?;
+        Ok(Index { root: root.to_path_buf(), entries })
+    }
+
+    /// Indexes the files under `root` and writes the index. With `update`,
+    /// entries of an existing index are kept for files that haven't changed.
+    /// Files that can't be read are added to `errors` and left out.
+    pub fn build(root: &Path, update: bool, errors: &mut Vec<MinigrepError>) -> Result<BuildStats, MinigrepError> {
+        let mut previous = match Index::load(root) {
+            Ok(index) if update => index.entries,
+            _ => HashMap::new(),
+        };
+
+        let roots = [root.to_string_lossy().into_owned()];
+        let files = walk::collect_files(&roots, &WalkOptions::default(), errors)?;
+        let mut entries = HashMap::new();
+        let mut stats = BuildStats::default();
+
+        for file in files {
+            let relative = match file.strip_prefix(root) {
+                Ok(relative) if relative.to_str().is_some() => relative.to_path_buf(),
+                // Files without a UTF-8 path aren't indexed, so they're always searched.
+                _ => continue,
+            };
+            let metadata = match fs::metadata(&file) {
+                Ok(metadata) => metadata,
+                Err(source) => {
+                    errors.push(MinigrepError::Io { path: file, source });
+                    continue;
+                }
+            };
+
+            let entry = match previous.remove(&relative) {
+                Some(entry) if entry.is_fresh(&metadata) => entry,
+                _ => {
+                    let trigrams = input::open(&file, &InputOptions::default()).and_then(trigrams);
+                    match trigrams {
+                        Ok(trigrams) => {
+                            stats.read += 1;
+                            Entry { modified: modified(&metadata), size: metadata.len(), trigrams }
+                        }
+                        Err(err) => {
+                            errors.push(MinigrepError::read(&file, err));
+                            continue;
+                        }
+                    }
+                }
+            };
+            entries.insert(relative, entry);
+        }
+
+        stats.files = entries.len() as u64;
+        let index = Index { root: root.to_path_buf(), entries };
+        index.save()?;
+        Ok(stats)
+    }
+
+    /// Writes the index next to a temporary name first, so a search never
+    /// sees a half-written index.
+    fn save(&self) -> Result<(), MinigrepError> {
+        let path = Index::path(&self.root);
+        let temp = self.root.join(format!("{}.{}.tmp", INDEX_FILE, std::process::id()));
+        let io_error = |source| MinigrepError::Io { path: path.clone(), source };
+
+        let result = File::create(&temp)
+            .and_then(|file| {
+                let mut out = BufWriter::new(file);
+                self.write_entries(&mut out)?;
+                out.into_inner().map_err(|err| err.into_error())?.sync_all()
+            })
+            .and_then(|()| fs::rename(&temp, &path));
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result.map_err(io_error)
+    }
+
+    fn write_entries<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        // Sorted so the same tree always gives the same index.
+        let mut paths: Vec<&PathBuf> = self.entries.keys().collect();
+        paths.sort();
+
+        out.write_all(MAGIC)?;
+        write_u32(out, paths.len())?;
+        for path in paths {
+            let entry = &self.entries[path];
+            let path = path.to_str().unwrap_or_default();
+
+            write_u32(out, path.len())?;
+            out.write_all(path.as_bytes())?;
+            out.write_all(&entry.modified.0.to_le_bytes())?;
+            out.write_all(&entry.modified.1.to_le_bytes())?;
+            out.write_all(&entry.size.to_le_bytes())?;
+            write_u32(out, entry.trigrams.len())?;
+            for trigram in &entry.trigrams {
+                out.write_all(&trigram.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether the file at `path` may match `query`: it does unless
+    /// it's indexed, unchanged and lacks the trigrams of every alternative.
+    pub fn may_match(&self, path: &Path, query: &Query) -> bool {
+        let entry = match path.strip_prefix(&self.root).ok().and_then(|relative| self.entries.get(relative)) {
+            Some(entry) => entry,
+            None => return true,
+        };
+
+        match fs::metadata(path) {
+            Ok(metadata) if entry.is_fresh(&metadata) => query.matches(&entry.trigrams),
+            _ => true,
+        }
+    }
+}
+
+impl Entry {
+    fn is_fresh(&self, metadata: &fs::Metadata) -> bool {
+        self.size == metadata.len() && self.modified != (0, 0) && self.modified == modified(metadata)
+    }
+}
+
+/// Returns the modification time, or zero when the platform doesn't have one.
+fn modified(metadata: &fs::Metadata) -> (u64, u32) {
+    metadata.modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or((0, 0), |since| (since.as_secs(), since.subsec_nanos()))
+}
+
+/// Collects the trigrams of the case-folded contents of `reader`.
+fn trigrams<R: BufRead>(mut reader: R) -> io::Result<Vec<u32>> {
+    let mut set = HashSet::new();
+    let mut window = Trigrams::default();
+    let mut buf = Vec::new();
+
+    while reader.read_until(b'\n', &mut buf)? > 0 {
+        let folded = casefold::fold(&String::from_utf8_lossy(&buf));
+        set.extend(folded.bytes().filter_map(|byte| window.push(byte)));
+        buf.clear();
+    }
+
+    let mut trigrams: Vec<u32> = set.into_iter().collect();
+    trigrams.sort_unstable();
+    Ok(trigrams)
+}
+
+/// The last three bytes seen, packed into an integer.
+#[derive(Default)]
+struct Trigrams {
+    window: u32,
+    len: usize,
+}
+
+impl Trigrams {
+    fn push(&mut self, byte: u8) -> Option<u32> {
+        self.window = (self.window << 8 | u32::from(byte)) & 0xff_ffff;
+        self.len += 1;
+        (self.len >= 3).then_some(self.window)
+    }
+}
+
+/// The trigrams a file must contain to possibly match a search: all those
+/// of at least one alternative.
+pub struct Query {
+    alternatives: Vec<Vec<u32>>,
+}
+
+impl Query {
+    /// Builds the query for a search, or `None` when the index can't rule
+    /// out any file for it.
+    pub fn new(config: &Config) -> Option<Query> {
+        let can_select_without_match = config.invert_match || config.output == OutputMode::FilesWithoutMatches;
+        // The index was built from input decoded with the default options.
+        let default_input = config.input.decompress == Decompress::Auto && config.input.encoding.is_none();
+        // Approximate matches needn't contain any trigram of the pattern.
+        if can_select_without_match || !default_input || config.fuzzy.is_some() || config.patterns.is_empty() {
+            return None;
+        }
+
+        let case_sensitive = config.case.is_sensitive(&config.patterns);
+        let mut alternatives = Vec::new();
+
+        for pattern in &config.patterns {
+            let literals = if config.regex {
+                required_prefixes(pattern, case_sensitive, config.multiline)?
+            } else {
+                vec![pattern.clone().into_bytes()]
+            };
+
+            for literal in literals {
+                let trigrams = literal_trigrams(&literal);
+                if trigrams.is_empty() {
+                    return None;
+                }
+                alternatives.push(trigrams);
+            }
+        }
+
+        Some(Query { alternatives })
+    }
+
+    fn matches(&self, trigrams: &[u32]) -> bool {
+        self.alternatives.iter()
+            .any(|required| required.iter().all(|trigram| trigrams.binary_search(trigram).is_ok()))
+    }
+}
+
+/// Returns literals one of which every match of `pattern` starts with, or
+/// `None` if there's no such finite set.
+fn required_prefixes(pattern: &str, case_sensitive: bool, multiline: bool) -> Option<Vec<Vec<u8>>> {
+    let hir = ParserBuilder::new()
+        .case_insensitive(!case_sensitive)
+        .multi_line(multiline)
+        .build()
+        .parse(pattern)
+        .ok()?;
+
+    let literals = Extractor::new().extract(&hir);
+    Some(literals.literals()?.iter().map(|literal| literal.as_bytes().to_vec()).collect())
+}
+
+/// Returns the trigrams of the case-folded whole characters of `literal`.
+fn literal_trigrams(literal: &[u8]) -> Vec<u32> {
+    // A prefix may end inside a character; only whole ones can be folded.
+    let text = match std::str::from_utf8(literal) {
+        Ok(text) => text,
+        Err(err) => std::str::from_utf8(&literal[..err.valid_up_to()]).unwrap_or_default(),
+    };
+
+    let mut window = Trigrams::default();
+    let mut trigrams: Vec<u32> = casefold::fold(text).bytes().filter_map(|byte| window.push(byte)).collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+/// Drops the files under indexed directories that can't match, using the
+/// index of each directory given on the command line.
+pub fn narrow(files: Vec<PathBuf>, config: &Config) -> Result<Vec<PathBuf>, MinigrepError> {
+    let query = match Query::new(config) {
+        Some(query) => query,
+        None => return Ok(files),
+    };
+
+    let mut indexes = Vec::new();
+    for dir in config.filenames.iter().map(Path::new).filter(|path| path.is_dir()) {
+        if !Index::path(dir).is_file() {
+            return Err(MinigrepError::Usage(format!(
+                "{} isn't indexed; run 'minigrep index build {}' first",
+                dir.display(),
+                dir.display()
+            )));
+        }
+        indexes.push(Index::load(dir)?);
+    }
+
+    Ok(files.into_iter()
+        .filter(|file| indexes.iter().all(|index| index.may_match(file, &query)))
+        .collect())
+}
+
+fn read_entries<R: Read>(mut reader: R) -> io::Result<HashMap<PathBuf, Entry>> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a minigrep index, or from another version"));
+    }
+
+    let count = read_u32(&mut reader)?;
+    let mut entries = HashMap::with_capacity(count);
+
+    for _ in 0..count {
+        let mut path = vec![0; read_u32(&mut reader)?];
+        reader.read_exact(&mut path)?;
+        let path = String::from_utf8(path).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let modified = (read_u64(&mut reader)?, read_u32(&mut reader)? as u32);
+        let size = read_u64(&mut reader)?;
+        let trigrams = (0..read_u32(&mut reader)?)
+            .map(|_| read_u32(&mut reader).map(|trigram| trigram as u32))
+            .collect::<io::Result<_>>()?;
+
+        entries.insert(PathBuf::from(path), Entry { modified, size, trigrams });
+    }
+
+    Ok(entries)
+}
+
+fn write_u32<W: Write>(out: &mut W, n: usize) -> io::Result<()> {
+    let n = u32::try_from(n).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    out.write_all(&n.to_le_bytes())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes) as usize)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CaseMode;
+    use std::env;
+
+    fn query(patterns: &[&str], regex: bool, case: CaseMode) -> Option<Query> {
+        let config = Config {
+            patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            regex,
+            case,
+            ..Config::default()
+        };
+        Query::new(&config)
+    }
+
+    fn file_trigrams(contents: &str) -> Vec<u32> {
+        trigrams(contents.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn literal_queries() {
+        let poem = file_trigrams("How dreary to be somebody!\nHow public, like a frog\n");
+
+        assert!(query(&["frog"], false, CaseMode::Sensitive).unwrap().matches(&poem));
+        assert!(query(&["FROG"], false, CaseMode::Insensitive).unwrap().matches(&poem));
+        assert!(query(&["toad", "body!\nHow"], false, CaseMode::Sensitive).unwrap().matches(&poem));
+        assert!(!query(&["toad"], false, CaseMode::Sensitive).unwrap().matches(&poem));
+        assert!(query(&["fr"], false, CaseMode::Sensitive).is_none());
+    }
+
+    #[test]
+    fn folded_text_matches_folded_queries() {
+        let text = file_trigrams("Die Straße\n");
+
+        assert!(query(&["STRASSE"], false, CaseMode::Insensitive).unwrap().matches(&text));
+        assert!(query(&["Straße"], false, CaseMode::Sensitive).unwrap().matches(&text));
+    }
+
+    #[test]
+    fn regex_queries() {
+        let source = file_trigrams("fn main() {\n    run();\n}\n");
+
+        assert!(query(&[r"fn \w+\("], true, CaseMode::Sensitive).unwrap().matches(&source));
+        assert!(query(&["(main|helper)"], true, CaseMode::Sensitive).unwrap().matches(&source));
+        assert!(!query(&["(toad|helper)"], true, CaseMode::Sensitive).unwrap().matches(&source));
+        assert!(query(&[r"\w+\(\)"], true, CaseMode::Sensitive).is_none());
+    }
+
+    #[test]
+    fn searches_that_need_every_file() {
+        let config = Config { patterns: vec![String::from("frog")], invert_match: true, ..Config::default() };
+        assert!(Query::new(&config).is_none());
+
+        let config = Config {
+            patterns: vec![String::from("frog")],
+            output: OutputMode::FilesWithoutMatches,
+            ..Config::default()
+        };
+        assert!(Query::new(&config).is_none());
+    }
+
+    #[test]
+    fn build_update_and_narrow() {
+        let root = env::temp_dir().join(format!("minigrep-index-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("frog.txt"), "How public, like a frog\n").unwrap();
+        fs::write(root.join("toad.txt"), "To an admiring bog!\n").unwrap();
+
+        let mut errors = Vec::new();
+        let built = Index::build(&root, false, &mut errors).unwrap();
+        let updated = Index::build(&root, true, &mut errors).unwrap();
+
+        let config = Config {
+            patterns: vec![String::from("frog")],
+            filenames: vec![root.to_string_lossy().into_owned()],
+            ..Config::default()
+        };
+        let files = vec![root.join("frog.txt"), root.join("toad.txt"), root.join("new.txt")];
+        let narrowed = narrow(files.clone(), &config).unwrap();
+
+        // A changed file is searched again even though its entry says otherwise.
+        fs::write(root.join("toad.txt"), "A frog and a toad\n").unwrap();
+        let after_change = narrow(files.clone(), &config).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(errors.is_empty());
+        assert_eq!(BuildStats { files: 2, read: 2 }, built);
+        assert_eq!(BuildStats { files: 2, read: 0 }, updated);
+        assert_eq!(vec![root.join("frog.txt"), root.join("new.txt")], narrowed);
+        assert_eq!(files, after_change);
+    }
+
+    #[test]
+    fn missing_index() {
+        let config = Config {
+            patterns: vec![String::from("frog")],
+            filenames: vec![String::from("src")],
+            ..Config::default()
+        };
+
+        assert!(matches!(narrow(Vec::new(), &config), Err(MinigrepError::Usage(_))));
+    }
+}
@@ -0,0 +1,328 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+use bzip2::bufread::MultiBzDecoder;
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::bufread::MultiGzDecoder;
+use memmap2::Mmap;
+
+/// When to decompress input (`-z`, `--no-decompress`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decompress {
+    /// Decompress input with a known extension or magic number.
+    Auto,
+    /// Require every input to be compressed in a known format.
+    Always,
+    Never,
+}
+
+/// A compression format minigrep can read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Format {
+    /// Detects the format from the file extension, then from the first bytes.
+    ///
+    /// A format guessed from the bytes alone must also decode: text that
+    /// merely starts like a compressed stream is taken for plain text.
+    pub fn detect(path: &Path, head: &[u8]) -> Option<Format> {
+        let by_extension = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") | Some("tgz") => Some(Format::Gzip),
+            Some("zst") | Some("zstd") => Some(Format::Zstd),
+            Some("bz2") | Some("tbz2") => Some(Format::Bzip2),
+            _ => None,
+        };
+
+        by_extension.or_else(|| Format::sniff(head).filter(|&format| format.decodes(head)))
+    }
+
+    fn sniff(head: &[u8]) -> Option<Format> {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::Gzip)
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::Zstd)
+        } else if is_bzip2(head) {
+            Some(Format::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    /// Whether `head`, the start of an input, decodes in this format. Running
+    /// out of input is fine; the rest of the stream isn't in `head`.
+    fn decodes(self, head: &[u8]) -> bool {
+        let mut byte = [0];
+        match self.decoder(head).and_then(|mut decoder| decoder.read(&mut byte)) {
+            Ok(_) => true,
+            Err(err) => err.kind() == io::ErrorKind::UnexpectedEof,
+        }
+    }
+
+    fn decoder<'a, R: BufRead + 'a>(self, reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+        Ok(match self {
+            Format::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+            Format::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+            Format::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+        })
+    }
+}
+
+/// Whether `head` starts a bzip2 stream: `BZh`, the block size and the magic
+/// number of either a block or the end of the stream.
+fn is_bzip2(head: &[u8]) -> bool {
+    const BLOCK: &[u8] = &[0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+    const END: &[u8] = &[0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+
+    head.len() >= 10
+        && head.starts_with(b"BZh")
+        && (b'1'..=b'9').contains(&head[3])
+        && (&head[4..10] == BLOCK || &head[4..10] == END)
+}
+
+/// How files are read (`--mmap`, `--no-mmap`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadStrategy {
+    /// Memory-map regular files of at least `MMAP_THRESHOLD` bytes and read
+    /// everything else through a buffer.
+    Auto,
+    /// Memory-map every regular file.
+    Mmap,
+    Buffered,
+}
+
+/// Size from which `ReadStrategy::Auto` memory-maps a file. Below it, setting
+/// up the mapping costs more than the copies it saves.
+pub const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// How inputs are opened and decoded before they are searched.
+#[derive(Clone, Debug)]
+pub struct InputOptions {
+    pub decompress: Decompress,
+    pub strategy: ReadStrategy,
+    /// Encoding of the input (`--encoding`). Without one, input with a
+    /// UTF-16 or UTF-8 byte order mark is decoded accordingly and anything
+    /// else is read as UTF-8.
+    pub encoding: Option<&'static Encoding>,
+}
+
+impl Default for InputOptions {
+    fn default() -> InputOptions {
+        InputOptions { decompress: Decompress::Auto, strategy: ReadStrategy::Auto, encoding: None }
+    }
+}
+
+/// Looks up an encoding by one of its WHATWG labels, such as `utf-16le`,
+/// `latin1` or `shift_jis`.
+pub fn encoding_for_label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+/// Returns whether the file at `path` looks compressed.
+pub fn is_compressed(path: &Path) -> io::Result<bool> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(Format::detect(path, reader.fill_buf()?).is_some())
+}
+
+/// Returns whether the file at `path` starts with a UTF-16 byte order mark,
+/// so it's transcoded before it's searched.
+pub fn is_utf16(path: &Path) -> io::Result<bool> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let head = reader.fill_buf()?;
+    Ok(head.starts_with(&[0xff, 0xfe]) || head.starts_with(&[0xfe, 0xff]))
+}
+
+/// Opens `path`, or standard input for `-`, as a buffered reader of its
+/// decompressed contents. Large regular files are memory-mapped according
+/// to the read strategy; pipes and other special files never are.
+pub fn open(path: &Path, options: &InputOptions) -> io::Result<Box<dyn BufRead>> {
+    if path == Path::new("-") {
+        return decode(path, io::stdin().lock(), options);
+    }
+
+    let file = File::open(path)?;
+    match mmap(&file, options.strategy)? {
+        Some(map) => decode(path, Cursor::new(map), options),
+        None => decode(path, BufReader::new(file), options),
+    }
+}
+
+fn mmap(file: &File, strategy: ReadStrategy) -> io::Result<Option<Mmap>> {
+    let metadata = file.metadata()?;
+    let wanted = match strategy {
+        ReadStrategy::Auto => metadata.len() >= MMAP_THRESHOLD,
+        ReadStrategy::Mmap => metadata.len() > 0,
+        ReadStrategy::Buffered => false,
+    };
+    if !wanted || !metadata.is_file() {
+        return Ok(None);
+    }
+
+    // SAFETY: the mapping is only read. If another process truncates the
+    // file while it's searched, reading past the new end fails with SIGBUS,
+    // the same trade-off other grep tools make for the speed of mmap.
+    let map = unsafe { Mmap::map(file) };
+    // Fall back to reading when the file system can't be mapped.
+    Ok(map.ok())
+}
+
+fn decode<'a, R: BufRead + 'a>(path: &Path, reader: R, options: &InputOptions) -> io::Result<Box<dyn BufRead + 'a>> {
+    let reader = decompress(path, reader, options)?;
+    transcode(reader, options)
+}
+
+fn decompress<'a, R: BufRead + 'a>(path: &Path, mut reader: R, options: &InputOptions) -> io::Result<Box<dyn BufRead + 'a>> {
+    if options.decompress == Decompress::Never {
+        return Ok(Box::new(reader));
+    }
+
+    let format = Format::detect(path, reader.fill_buf()?);
+
+    match (format, options.decompress) {
+        (Some(format), _) => format.decoder(reader),
+        (None, Decompress::Always) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not in a known compressed format (gzip, zstd or bzip2)",
+        )),
+        (None, _) => Ok(Box::new(reader)),
+    }
+}
+
+/// Converts the input to UTF-8 when it isn't already.
+fn transcode<'a>(mut reader: Box<dyn BufRead + 'a>, options: &InputOptions) -> io::Result<Box<dyn BufRead + 'a>> {
+    let has_bom = {
+        let head = reader.fill_buf()?;
+        head.starts_with(&[0xff, 0xfe]) || head.starts_with(&[0xfe, 0xff]) || head.starts_with(&[0xef, 0xbb, 0xbf])
+    };
+
+    match options.encoding {
+        Some(encoding) if encoding != encoding_rs::UTF_8 || has_bom => {}
+        None if has_bom => {}
+        _ => return Ok(reader),
+    }
+
+    let decoder = DecodeReaderBytesBuilder::new()
+        .encoding(options.encoding)
+        .bom_override(true)
+        .strip_bom(true)
+        .build(reader);
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::io::{Read, Write};
+
+    const TEXT: &str = "How public, like a frog\nTo an admiring bog!\n";
+
+    fn read_all(path: &str, data: &[u8], decompress: Decompress) -> io::Result<String> {
+        let options = InputOptions { decompress, ..InputOptions::default() };
+        read_with(path, data, &options)
+    }
+
+    fn read_with(path: &str, data: &[u8], options: &InputOptions) -> io::Result<String> {
+        let mut contents = String::new();
+        decode(Path::new(path), data, options)?.read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    #[test]
+    fn gzip_by_magic_bytes() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(TEXT.as_bytes()).unwrap();
+        let data = encoder.finish().unwrap();
+
+        assert_eq!(TEXT, read_all("poem", &data, Decompress::Auto).unwrap());
+        assert_ne!(Some(String::from(TEXT)), read_all("poem", &data, Decompress::Never).ok());
+    }
+
+    #[test]
+    fn zstd_frames() {
+        let mut data = zstd::encode_all(&b"How public, like a frog\n"[..], 0).unwrap();
+        data.extend(zstd::encode_all(&b"To an admiring bog!\n"[..], 0).unwrap());
+
+        assert_eq!(TEXT, read_all("poem.zst", &data, Decompress::Auto).unwrap());
+    }
+
+    #[test]
+    fn bzip2_by_extension() {
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        encoder.write_all(TEXT.as_bytes()).unwrap();
+        let data = encoder.finish().unwrap();
+
+        assert_eq!(TEXT, read_all("poem.txt.bz2", &data, Decompress::Auto).unwrap());
+        assert_eq!(TEXT, read_all("poem", &data, Decompress::Auto).unwrap());
+    }
+
+    #[test]
+    fn text_that_looks_compressed() {
+        let bz = "BZh is how the bzip2 magic starts\n";
+        assert_eq!(bz, read_all("bz.txt", bz.as_bytes(), Decompress::Auto).unwrap());
+
+        let fake = b"BZh91AY&SY but not really bzip2\n";
+        assert_eq!(String::from_utf8_lossy(fake), read_all("fake", fake, Decompress::Auto).unwrap());
+
+        assert_eq!(None, Format::detect(Path::new("gz"), b"\x1f\x8b not gzip either\n"));
+        assert!(read_all("bz.txt", bz.as_bytes(), Decompress::Always).is_err());
+    }
+
+    #[test]
+    fn utf16_with_bom() {
+        let mut data = vec![0xff, 0xfe];
+        data.extend("カエル frog\n".encode_utf16().flat_map(u16::to_le_bytes));
+
+        assert_eq!("カエル frog\n", read_all("poem.txt", &data, Decompress::Auto).unwrap());
+    }
+
+    #[test]
+    fn utf8_bom_is_stripped() {
+        assert_eq!("frog\n", read_all("poem.txt", b"\xef\xbb\xbffrog\n", Decompress::Auto).unwrap());
+    }
+
+    #[test]
+    fn labelled_encodings() {
+        let shift_jis = InputOptions { encoding: encoding_for_label("shift_jis"), ..InputOptions::default() };
+        let (data, _, _) = encoding_rs::SHIFT_JIS.encode("カエルみたいで公すぎるじゃない。\n");
+        assert_eq!("カエルみたいで公すぎるじゃない。\n", read_with("poem.txt", &data, &shift_jis).unwrap());
+
+        let latin1 = InputOptions { encoding: encoding_for_label("latin1"), ..InputOptions::default() };
+        assert_eq!("café\n", read_with("menu.txt", b"caf\xe9\n", &latin1).unwrap());
+
+        assert!(encoding_for_label("klingon").is_none());
+    }
+
+    #[test]
+    fn read_strategies() {
+        let path = std::env::temp_dir().join(format!("minigrep-mmap-{}.txt", std::process::id()));
+        fs::write(&path, TEXT).unwrap();
+
+        let read = |strategy| {
+            let options = InputOptions { strategy, ..InputOptions::default() };
+            let mut contents = String::new();
+            open(&path, &options).unwrap().read_to_string(&mut contents).unwrap();
+            contents
+        };
+        let mapped = read(ReadStrategy::Mmap);
+        let buffered = read(ReadStrategy::Buffered);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(TEXT, mapped);
+        assert_eq!(TEXT, buffered);
+        if cfg!(unix) {
+            assert!(mmap(&File::open("/dev/null").unwrap(), ReadStrategy::Mmap).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn plain_text() {
+        assert_eq!(TEXT, read_all("poem.txt", TEXT.as_bytes(), Decompress::Auto).unwrap());
+        assert!(read_all("poem.txt", TEXT.as_bytes(), Decompress::Always).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! JSON Lines output for `--json`.
+//!
+//! Each record is a JSON object on its own line with a `type` field. Keys
+//! always appear in the order shown and are never omitted; new keys may be
+//! added at the end of a record, so consumers should ignore unknown keys.
+//!
+//! For every file with at least one selected line:
+//!
+//! ```text
+//! {"type":"begin","path":"poem.txt"}
+//! {"type":"match","path":"poem.txt","line_number":7,"offset":142,"column":20,"line":"How public, like a frog","submatches":[{"match":"frog","start":19,"end":23}]}
+//! {"type":"end","path":"poem.txt","matches":1}
+//! ```
+//!
+//! - `match` records are selected lines and `context` records are lines
+//!   printed because of `-A`, `-B` or `-C`; both have the same fields.
+//! - `line_number` is 1-based. `offset` is the byte offset of the start of
+//!   the line in the file, after decompression and decoding to UTF-8.
+//! - `column` is the 1-based byte column of the first submatch, or `null`
+//!   when there is none (context lines and lines selected with `-v`).
+//! - `line` is the line without its terminator. Invalid UTF-8 is replaced
+//!   with U+FFFD. With `-U`, a match record holds the whole block of lines
+//!   the match covers, joined by their terminators, and `line_number` is
+//!   the number of its first line.
+//! - `submatches` holds every match in the line, with `start` and `end` as
+//!   byte offsets into `line`.
+//! - `matches` in `end` is the number of selected lines in the file.
+//!
+//! After all files, one summary record is written, even if nothing matched:
+//!
+//! ```text
+//! {"type":"summary","files":3,"files_with_matches":1,"matches":1}
+//! ```
+//!
+//! Paths that are not valid UTF-8 are converted lossily.
+
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::printer::Stats;
+
+pub fn write_begin<W: Write>(out: &mut W, path: &Path) -> io::Result<()> {
+    write!(out, "{{\"type\":\"begin\",\"path\":")?;
+    write_string(out, &path.to_string_lossy())?;
+    writeln!(out, "}}")
+}
+
+pub fn write_line<W: Write>(
+    out: &mut W,
+    record: &str,
+    path: &Path,
+    line_number: usize,
+    offset: usize,
+    line: &str,
+    submatches: &[Range<usize>],
+) -> io::Result<()> {
+    write!(out, "{{\"type\":\"{}\",\"path\":", record)?;
+    write_string(out, &path.to_string_lossy())?;
+    write!(out, ",\"line_number\":{},\"offset\":{},\"column\":", line_number, offset)?;
+    match submatches.first() {
+        Some(range) => write!(out, "{}", range.start + 1)?,
+        None => write!(out, "null")?,
+    }
+    write!(out, ",\"line\":")?;
+    write_string(out, line)?;
+
+    write!(out, ",\"submatches\":[")?;
+    for (i, range) in submatches.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "{{\"match\":")?;
+        write_string(out, &line[range.clone()])?;
+        write!(out, ",\"start\":{},\"end\":{}}}", range.start, range.end)?;
+    }
+    writeln!(out, "]}}")
+}
+
+pub fn write_end<W: Write>(out: &mut W, path: &Path, matches: u64) -> io::Result<()> {
+    write!(out, "{{\"type\":\"end\",\"path\":")?;
+    write_string(out, &path.to_string_lossy())?;
+    writeln!(out, ",\"matches\":{}}}", matches)
+}
+
+pub fn write_summary<W: Write>(out: &mut W, stats: &Stats) -> io::Result<()> {
+    writeln!(
+        out,
+        "{{\"type\":\"summary\",\"files\":{},\"files_with_matches\":{},\"matches\":{}}}",
+        stats.files, stats.files_with_matches, stats.matches
+    )
+}
+
+fn write_string<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    write!(out, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        let mut out = Vec::new();
+        write_string(&mut out, "a \"quote\" \\ tab\t nul\0 é").unwrap();
+
+        assert_eq!(r#""a \"quote\" \\ tab\t nul\u0000 é""#, String::from_utf8(out).unwrap());
+    }
+}
@@ -1,70 +1,356 @@
-use std::fs::File;
-use std::io::prelude::*;
-use std::error::Error;
-use std::env;
+use std::io;
+use std::path::Path;
 
+pub mod args;
+pub mod casefold;
+pub mod defaults;
+pub mod error;
+pub mod fuzzy;
+pub mod index;
+pub mod input;
+pub mod json;
+pub mod matcher;
+pub mod parallel;
+pub mod printer;
+pub mod replace;
+pub mod searcher;
+pub mod walk;
+pub mod watch;
+
+pub use error::MinigrepError;
+
+use fuzzy::Fuzzy;
+use input::InputOptions;
+use matcher::{Boundary, Bounded, Matcher, Substring};
+use printer::{ColorChoice, OutputMode, Printer};
+use searcher::{BinaryMode, Searcher, Sink};
+use walk::WalkOptions;
+
+#[derive(Debug)]
 pub struct Config {
-    pub query: String,
-    pub filename: String,
-    pub case_sensitive: bool,
+    pub patterns: Vec<String>,
+    pub filenames: Vec<String>,
+    pub case: CaseMode,
+    pub regex: bool,
+    /// Only match whole words (`-w`).
+    pub word: bool,
+    /// Only match whole lines (`-x`).
+    pub line: bool,
+    /// Let matches span several lines (`-U`).
+    pub multiline: bool,
+    /// Match within this many edits of the patterns (`--fuzzy`).
+    pub fuzzy: Option<usize>,
+    /// Print each file's selected lines closest first (`--sort-by-distance`).
+    pub sort_by_distance: bool,
+    pub line_number: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub invert_match: bool,
+    pub output: OutputMode,
+    /// Stop searching a file after this many selected lines (`-m`).
+    pub max_count: Option<u64>,
+    /// Truncate printed lines longer than this many characters.
+    pub max_columns: Option<usize>,
+    /// Don't print errors about files that can't be read (`--no-messages`).
+    pub no_messages: bool,
+    /// Skip files that the trigram index rules out (`--index`).
+    pub index: bool,
+    /// Keep following the files and print newly selected lines (`--watch`).
+    pub watch: bool,
+    pub color: ColorChoice,
+    /// Number of files searched concurrently.
+    pub threads: usize,
+    pub walk: WalkOptions,
+    /// How files that look binary are handled.
+    pub binary: BinaryMode,
+    /// Text that replaces each match in the printed lines (`--replace`).
+    pub replacement: Option<String>,
+    /// Also write the replacements back to the files (`--in-place`).
+    pub in_place: bool,
+    /// Keep the original of each rewritten file with this suffix (`--backup`).
+    pub backup_suffix: Option<String>,
+    pub input: InputOptions,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            patterns: Vec::new(),
+            filenames: vec![String::from("-")],
+            case: CaseMode::Sensitive,
+            regex: false,
+            word: false,
+            line: false,
+            multiline: false,
+            fuzzy: None,
+            sort_by_distance: false,
+            line_number: false,
+            before_context: 0,
+            after_context: 0,
+            invert_match: false,
+            output: OutputMode::Lines,
+            max_count: None,
+            max_columns: None,
+            no_messages: false,
+            index: false,
+            watch: false,
+            color: ColorChoice::Auto,
+            threads: 1,
+            walk: WalkOptions::default(),
+            binary: BinaryMode::Skip,
+            replacement: None,
+            in_place: false,
+            backup_suffix: None,
+            input: InputOptions::default(),
+        }
+    }
 }
 
 impl Config {
-    pub fn new(mut args: std::env::Args) -> Result<Config, &'static str> {
-        args.next();
+    /// Builds the matcher selected by this configuration.
+    pub fn matcher(&self) -> Result<Box<dyn Matcher>, MinigrepError> {
+        let case_sensitive = self.case.is_sensitive(&self.patterns);
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
-        };
+        if let Some(max_distance) = self.fuzzy {
+            let matcher: Box<dyn Matcher> = Box::new(Fuzzy::new(&self.patterns, max_distance, case_sensitive));
+            return Ok(match self.boundary() {
+                Some(boundary) => Box::new(Bounded::new(matcher, boundary)),
+                None => matcher,
+            });
+        }
+        matcher::build(&self.patterns, self.regex, case_sensitive, self.boundary(), self.multiline)
+    }
 
-        let filename = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file name"),
-        };
+    /// Builds the searcher selected by this configuration. Context lines are
+    /// only searched for when the output mode prints lines.
+    pub fn searcher(&self) -> Result<Searcher, MinigrepError> {
+        let context = matches!(self.output, OutputMode::Lines | OutputMode::Json);
 
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+        Searcher::builder()
+            .matcher(self.matcher()?)
+            .invert_match(self.invert_match)
+            .max_count(self.max_count)
+            .before_context(if context { self.before_context } else { 0 })
+            .after_context(if context { self.after_context } else { 0 })
+            .binary(self.binary)
+            .input(self.input.clone())
+            .multiline(self.multiline)
+            .sort_by_distance(self.sort_by_distance)
+            .build()
+    }
 
-        Ok(Config { query, filename, case_sensitive })
+    fn boundary(&self) -> Option<Boundary> {
+        if self.line {
+            Some(Boundary::Line)
+        } else if self.word {
+            Some(Boundary::Word)
+        } else {
+            None
+        }
     }
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let mut f = File::open(config.filename)?;
+/// How letter case is compared.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless a pattern contains an uppercase letter.
+    Smart,
+}
+
+impl CaseMode {
+    pub fn is_sensitive<S: AsRef<str>>(self, patterns: &[S]) -> bool {
+        match self {
+            CaseMode::Sensitive => true,
+            CaseMode::Insensitive => false,
+            CaseMode::Smart => patterns.iter()
+                .any(|pattern| pattern.as_ref().chars().any(char::is_uppercase)),
+        }
+    }
+}
+
+/// How a search ended.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Outcome {
+    /// Whether anything was selected: a matching line, or with `-L` a file
+    /// without one.
+    pub selected: bool,
+    /// Number of files that couldn't be searched.
+    pub errors: u64,
+    /// Whether output was suppressed with `-q`.
+    pub quiet: bool,
+}
+
+impl Outcome {
+    /// The exit status: 2 if an error occurred, else 0 if anything was
+    /// selected and 1 if not. Like grep, `-q` exits with 0 once anything
+    /// was selected, even after an error.
+    pub fn exit_code(&self) -> i32 {
+        match (self.errors, self.selected) {
+            (_, true) if self.quiet => 0,
+            (0, true) => 0,
+            (0, false) => 1,
+            _ => 2,
+        }
+    }
+}
+
+/// Runs the search. Errors about single files are printed to standard error
+/// (unless `--no-messages` is given) and counted in the outcome while the
+/// search goes on; other errors stop it.
+pub fn run(config: Config) -> Result<Outcome, MinigrepError> {
+    let searcher = config.searcher()?;
+    let mut outcome = Outcome::default();
+
+    let mut report = |err: MinigrepError| {
+        if !config.no_messages {
+            eprintln!("minigrep: {}", err);
+        }
+        outcome.errors += 1;
+    };
+
+    let mut walk_errors = Vec::new();
+    let files = walk::collect_files(&config.filenames, &config.walk, &mut walk_errors)?;
+    walk_errors.into_iter().for_each(&mut report);
+    let files = if config.index { index::narrow(files, &config)? } else { files };
+    let with_filename = files.len() > 1
+        || config.filenames.iter().any(|name| Path::new(name).is_dir());
+
+    let stdout = io::stdout();
+    let mut printer = Printer::new(stdout.lock());
+    printer.with_filename = with_filename;
+    printer.line_number = config.line_number;
+    printer.mode = config.output;
+    printer.color = config.color.enabled();
+    printer.max_columns = config.max_columns;
+    printer.replacement = config.replacement.clone();
 
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?;
+    if config.watch {
+        match watch::follow(files, &config, &searcher, &mut printer, &mut report)? {}
+    }
+
+    let mut selected = false;
+    let mut to_rewrite = Vec::new();
 
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
-    } else {
-        search_case_insensitive(&config.query, &contents)
+    parallel::search_files(&files, &searcher, &mut printer, config.threads, |path, count| {
+        let count = match count {
+            Ok(count) => count,
+            Err(err) if err.is_per_file() => {
+                report(err);
+                return Ok(true);
+            }
+            Err(err) => return Err(err),
+        };
+
+        if config.in_place && count > 0 {
+            to_rewrite.push(path.to_path_buf());
+        }
+
+        if config.output == OutputMode::FilesWithoutMatches {
+            selected |= count == 0;
+        } else {
+            selected |= count > 0;
+        }
+
+        Ok(!(selected && config.output == OutputMode::Quiet))
+    })?;
+    printer.finish().map_err(MinigrepError::Output)?;
+
+    if let (true, Some(replacement)) = (config.in_place, &config.replacement) {
+        for path in to_rewrite {
+            if let Err(err) = rewrite(&path, &searcher, replacement, config.backup_suffix.as_deref()) {
+                report(err);
+            }
+        }
+    }
+
+    outcome.selected = selected;
+    outcome.quiet = config.output == OutputMode::Quiet;
+    Ok(outcome)
+}
+
+/// Writes the replacements of `--in-place` back to the file at `path`.
+///
+/// The file is searched again with `searcher` and only the lines it selects
+/// are rewritten, so the file ends up as the `--replace` preview showed it.
+fn rewrite(path: &Path, searcher: &Searcher, replacement: &str, backup_suffix: Option<&str>) -> Result<(), MinigrepError> {
+    let unsupported = |message: &str| MinigrepError::Io {
+        path: path.to_path_buf(),
+        source: io::Error::new(io::ErrorKind::Unsupported, message),
     };
 
-    for line in results {
-        println!("{}", line);
+    if path == Path::new("-") {
+        return Err(unsupported("can't rewrite standard input in place"));
+    }
+    if input::is_compressed(path).map_err(|err| MinigrepError::read(path, err))? {
+        return Err(unsupported("can't rewrite a compressed file in place"));
+    }
+    if input::is_utf16(path).map_err(|err| MinigrepError::read(path, err))? {
+        return Err(unsupported("can't rewrite UTF-16 text in place"));
     }
 
-    Ok(())
+    let mut selected = SelectedLines(Vec::new());
+    searcher.search_path(path, &mut selected)?;
+
+    replace::rewrite_file(path, searcher.matcher(), replacement, &selected.0, backup_suffix)
+        .map(|_| ())
+        .map_err(|source| MinigrepError::Io { path: path.to_path_buf(), source })
 }
 
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-     contents.lines()
-        .filter(|line| line.contains(query))
-        .collect()
+/// Collects the numbers of the selected lines.
+struct SelectedLines(Vec<usize>);
+
+impl Sink for SelectedLines {
+    fn matched(&mut self, _: &Searcher, m: &Match) -> io::Result<bool> {
+        self.0.push(m.line_number);
+        Ok(true)
+    }
+}
+
+/// A line that matched the query.
+#[derive(Debug, PartialEq)]
+pub struct Match<'a> {
+    /// 1-based line number.
+    pub line_number: usize,
+    /// Byte offset of the start of the line within the searched contents.
+    pub offset: usize,
+    /// The line without its terminator. In multiline mode this holds every
+    /// line of the match, joined by their terminators.
+    pub line: &'a str,
+}
+
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    search_with(&Substring::new(query, true), contents)
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<Match<'a>> {
+    search_with(&Substring::new(query, false), contents)
+}
+
+pub fn search_with<'a>(matcher: &dyn Matcher, contents: &'a str) -> Vec<Match<'a>> {
+    let mut offset = 0;
 
-    contents.lines()
-        .filter(|line| line.to_lowercase().contains(&query))
+    contents.split_inclusive('\n')
+        .enumerate()
+        .map(|(index, raw)| {
+            let m = Match { line_number: index + 1, offset, line: trim_newline(raw) };
+            offset += raw.len();
+            m
+        })
+        .filter(|m| matcher.is_match(m.line))
         .collect()
 }
 
+pub(crate) fn trim_newline(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use matcher::RegexMatcher;
 
     #[test]
     fn case_sensitive() {
@@ -76,7 +362,7 @@ Pick three.
 Duct tape.";
 
         assert_eq!(
-            vec!["safe, fast, productive."],
+            vec![Match { line_number: 2, offset: 6, line: "safe, fast, productive." }],
             search(query, contents)
         );
     }
@@ -92,7 +378,81 @@ Trust me.";
 
         assert_eq!(
             vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, contents)
+            lines(search_case_insensitive(query, contents))
+        );
+    }
+
+    #[test]
+    fn regex_search() {
+        let matcher = RegexMatcher::new(r"^fn \w+", true).unwrap();
+        let contents = "\
+fn main() {
+    run();
+}
+pub fn run() {}
+fn helper() {}";
+
+        assert_eq!(
+            vec!["fn main() {", "fn helper() {}"],
+            lines(search_with(&matcher, contents))
+        );
+    }
+
+    #[test]
+    fn case_insensitive_unicode() {
+        let contents = "\
+Maßstab
+MASSSTAB
+Ingenieur
+İstanbul";
+
+        assert_eq!(vec!["Maßstab", "MASSSTAB"], lines(search_case_insensitive("massstab", contents)));
+        assert_eq!(vec!["İstanbul"], lines(search_case_insensitive("i̇stanbul", contents)));
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(!CaseMode::Smart.is_sensitive(&["frog", "ß"]));
+        assert!(CaseMode::Smart.is_sensitive(&["frog", "Toad"]));
+        assert!(CaseMode::Smart.is_sensitive(&["Émile"]));
+    }
+
+    #[test]
+    fn whole_words_and_lines() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+
+        let mut config = Config {
+            patterns: vec![String::from("duct")],
+            case: CaseMode::Smart,
+            word: true,
+            ..Config::default()
+        };
+        assert_eq!(vec!["Duct tape."], lines(search_with(config.matcher().unwrap().as_ref(), contents)));
+
+        config.patterns = vec![String::from("pick three.")];
+        config.word = false;
+        config.line = true;
+        assert_eq!(vec!["Pick three."], lines(search_with(config.matcher().unwrap().as_ref(), contents)));
+    }
+
+    #[test]
+    fn match_positions() {
+        let contents = "one\r\ntwo\nthree two";
+
+        assert_eq!(
+            vec![
+                Match { line_number: 2, offset: 5, line: "two" },
+                Match { line_number: 3, offset: 9, line: "three two" },
+            ],
+            search("two", contents)
         );
     }
+
+    fn lines<'a>(matches: Vec<Match<'a>>) -> Vec<&'a str> {
+        matches.iter().map(|m| m.line).collect()
+    }
 }
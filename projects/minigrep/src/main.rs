@@ -1,16 +1,68 @@
 extern crate minigrep;
 
 use std::env;
+use std::io;
+use std::path::Path;
 use std::process;
 
+use minigrep::args::{self, Command};
+use minigrep::index::Index;
+use minigrep::MinigrepError;
+
 fn main() {
-    let config = minigrep::Config::new(env::args()).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {}", err);
-        process::exit(1);
-    });
+    let config = match args::parse(env::args()) {
+        Ok(Command::Search(config)) => *config,
+        Ok(Command::Index { root, update }) => process::exit(build_index(&root, update)),
+        Ok(Command::Help) => {
+            println!("{}", args::USAGE);
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("minigrep {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(err) => {
+            eprintln!("Problem parsing arguments: {}", err);
+            eprintln!("Try 'minigrep --help' for more information.");
+            process::exit(2);
+        }
+    };
+
+    match minigrep::run(config) {
+        Ok(outcome) => process::exit(outcome.exit_code()),
+        // The reader of our output went away, as with `minigrep ... | head`.
+        Err(MinigrepError::Output(err)) if err.kind() == io::ErrorKind::BrokenPipe => process::exit(2),
+        Err(err @ MinigrepError::Usage(_)) => {
+            eprintln!("Problem parsing arguments: {}", err);
+            process::exit(2);
+        }
+        Err(err) => {
+            eprintln!("Application error: {}", err);
+            process::exit(2);
+        }
+    }
+}
+
+/// Builds or updates the index of `root` and returns the exit status.
+fn build_index(root: &Path, update: bool) -> i32 {
+    let mut errors = Vec::new();
 
-    if let Err(e) = minigrep::run(config) {
-        eprintln!("Application error: {}", e);
-        process::exit(1);
+    match Index::build(root, update, &mut errors) {
+        Ok(stats) => {
+            for err in &errors {
+                eprintln!("minigrep: {}", err);
+            }
+            println!(
+                "Indexed {} files ({} read) into {}",
+                stats.files,
+                stats.read,
+                Index::path(root).display()
+            );
+            if errors.is_empty() { 0 } else { 2 }
+        }
+        Err(err) => {
+            eprintln!("Application error: {}", err);
+            2
+        }
     }
 }
@@ -0,0 +1,627 @@
+use std::ops::Range;
+
+use aho_corasick::{AhoCorasick, Input, MatchKind};
+use regex::{Regex, RegexBuilder};
+
+use crate::casefold::{self, Folded};
+use crate::error::MinigrepError;
+
+/// Finds occurrences of a query inside a single line of text.
+///
+/// Matchers are shared between the threads searching files in parallel.
+pub trait Matcher: Send + Sync {
+    /// Returns the byte range of the first match at or after `start`.
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>>;
+
+    fn find(&self, line: &str) -> Option<Range<usize>> {
+        self.find_at(line, 0)
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        self.find(line).is_some()
+    }
+
+    /// Prepares repeated searches of `text`, returning a function that
+    /// finds the first match at or after a byte offset like `find_at`.
+    /// Matchers that transform the text before searching it, such as
+    /// case-insensitive ones, do it once here rather than on every call.
+    fn finder<'t>(&'t self, text: &'t str) -> Finder<'t> {
+        Box::new(move |start| self.find_at(text, start))
+    }
+
+    /// Returns the byte ranges of all non-overlapping matches in `line`.
+    fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        let find = self.finder(line);
+        let mut ranges = Vec::new();
+        let mut start = 0;
+
+        while start <= line.len() {
+            let range = match find(start) {
+                Some(range) => range,
+                None => break,
+            };
+
+            start = if range.is_empty() {
+                // Step over the next character so empty matches can't loop.
+                range.end + line[range.end..].chars().next().map_or(1, char::len_utf8)
+            } else {
+                range.end
+            };
+            if !range.is_empty() {
+                ranges.push(range);
+            }
+        }
+
+        ranges
+    }
+
+    /// Returns the shorter matches starting where the match at `range`
+    /// does, longest first. `Bounded` tries them when that match doesn't
+    /// respect its boundary. Matchers finding one length per start return
+    /// none.
+    fn shorter_matches(&self, _line: &str, _range: Range<usize>) -> Vec<Range<usize>> {
+        Vec::new()
+    }
+
+    /// Returns how many edits the best match in `line` is away from the
+    /// query, for matchers that match approximately. Exact matchers return
+    /// `None`.
+    fn distance(&self, _line: &str) -> Option<usize> {
+        None
+    }
+
+    /// Returns the text that replaces the match of `line` at `range` with
+    /// `--replace`. Plain matchers use `replacement` as is.
+    fn expand(&self, _line: &str, _range: Range<usize>, replacement: &str) -> String {
+        replacement.to_string()
+    }
+}
+
+/// Finds the first match at or after a byte offset of the text it was
+/// prepared for, see `Matcher::finder`.
+pub type Finder<'t> = Box<dyn Fn(usize) -> Option<Range<usize>> + 't>;
+
+/// Plain substring matching, the default mode. Case-insensitive matching
+/// compares Unicode case-folded text.
+pub struct Substring {
+    query: String,
+    case_sensitive: bool,
+}
+
+impl Substring {
+    pub fn new(query: &str, case_sensitive: bool) -> Substring {
+        let query = if case_sensitive {
+            query.to_string()
+        } else {
+            casefold::fold(query)
+        };
+
+        Substring { query, case_sensitive }
+    }
+}
+
+impl Matcher for Substring {
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        if self.case_sensitive {
+            line[start..].find(&self.query)
+                .map(|i| start + i..start + i + self.query.len())
+        } else {
+            let haystack = &line[start..];
+            let folded = Folded::new(haystack);
+
+            folded.text.find(&self.query)
+                .map(|i| folded.original_range(haystack, i..i + self.query.len()))
+                .map(|range| start + range.start..start + range.end)
+        }
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        if self.case_sensitive {
+            line.contains(&self.query)
+        } else {
+            casefold::fold(line).contains(&self.query)
+        }
+    }
+
+    fn finder<'t>(&'t self, text: &'t str) -> Finder<'t> {
+        if self.case_sensitive {
+            return Box::new(move |start| self.find_at(text, start));
+        }
+
+        let folded = Folded::new(text);
+        Box::new(move |start| {
+            let from = folded.folded_offset(start);
+            folded.text[from..].find(&self.query)
+                .map(|i| folded.original_range(text, from + i..from + i + self.query.len()))
+        })
+    }
+}
+
+/// Matches any of several plain patterns in a single pass over the line,
+/// using an Aho-Corasick automaton. Used for `-e` and `-f` patterns.
+pub struct MultiSubstring {
+    automaton: AhoCorasick,
+    case_sensitive: bool,
+    /// For `shorter_matches`, the patterns with overlapping matches.
+    overlapping: Option<AhoCorasick>,
+}
+
+impl MultiSubstring {
+    pub fn new<S: AsRef<str>>(patterns: &[S], case_sensitive: bool) -> Result<MultiSubstring, aho_corasick::BuildError> {
+        MultiSubstring::build(patterns, case_sensitive, false)
+    }
+
+    /// Like `new`, but also finds the shorter matches `Bounded` falls back
+    /// on: with `-w`, `-e foo -e foo-bar` still matches `foo-barx`.
+    pub fn with_shorter_matches<S: AsRef<str>>(
+        patterns: &[S],
+        case_sensitive: bool,
+    ) -> Result<MultiSubstring, aho_corasick::BuildError> {
+        MultiSubstring::build(patterns, case_sensitive, true)
+    }
+
+    fn build<S: AsRef<str>>(
+        patterns: &[S],
+        case_sensitive: bool,
+        shorter_matches: bool,
+    ) -> Result<MultiSubstring, aho_corasick::BuildError> {
+        let patterns: Vec<String> = patterns.iter()
+            .map(|pattern| {
+                if case_sensitive {
+                    pattern.as_ref().to_string()
+                } else {
+                    casefold::fold(pattern.as_ref())
+                }
+            })
+            .collect();
+
+        let automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(&patterns)?;
+        let overlapping = if shorter_matches { Some(AhoCorasick::new(&patterns)?) } else { None };
+
+        Ok(MultiSubstring { automaton, case_sensitive, overlapping })
+    }
+}
+
+impl Matcher for MultiSubstring {
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        if self.case_sensitive {
+            let input = Input::new(line).span(start..line.len());
+            self.automaton.find(input).map(|m| m.range())
+        } else {
+            let haystack = &line[start..];
+            let folded = Folded::new(haystack);
+
+            self.automaton.find(folded.text.as_str())
+                .map(|m| folded.original_range(haystack, m.range()))
+                .map(|range| start + range.start..start + range.end)
+        }
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        if self.case_sensitive {
+            self.automaton.is_match(line)
+        } else {
+            self.automaton.is_match(casefold::fold(line).as_str())
+        }
+    }
+
+    fn finder<'t>(&'t self, text: &'t str) -> Finder<'t> {
+        if self.case_sensitive {
+            return Box::new(move |start| self.find_at(text, start));
+        }
+
+        let folded = Folded::new(text);
+        Box::new(move |start| {
+            let input = Input::new(folded.text.as_str()).span(folded.folded_offset(start)..folded.text.len());
+            self.automaton.find(input).map(|m| folded.original_range(text, m.range()))
+        })
+    }
+
+    fn shorter_matches(&self, line: &str, range: Range<usize>) -> Vec<Range<usize>> {
+        let overlapping = match &self.overlapping {
+            Some(overlapping) => overlapping,
+            None => return Vec::new(),
+        };
+
+        // Any shorter match at the same start lies within the longest one.
+        let text = &line[range.clone()];
+        let mut ranges: Vec<Range<usize>> = if self.case_sensitive {
+            overlapping.find_overlapping_iter(text)
+                .filter(|m| m.start() == 0)
+                .map(|m| range.start..range.start + m.end())
+                .collect()
+        } else {
+            let folded = Folded::new(text);
+            overlapping.find_overlapping_iter(folded.text.as_str())
+                .filter(|m| m.start() == 0)
+                .map(|m| folded.original_range(text, m.range()))
+                .map(|found| range.start + found.start..range.start + found.end)
+                .collect()
+        };
+
+        ranges.retain(|found| found.end < range.end);
+        ranges.sort_by_key(|found| usize::MAX - found.end);
+        ranges.dedup();
+        ranges
+    }
+}
+
+/// Regular-expression matching, selected with `--regex`.
+pub struct RegexMatcher {
+    regex: Regex,
+    /// Set when `regex` ends with a group matching what must follow a
+    /// match, see `RegexMatcher::followed_by`. It's then the patterns alone,
+    /// anchored at the end of the text, for expanding a match without what
+    /// follows it.
+    unfollowed: Option<Regex>,
+}
+
+impl RegexMatcher {
+    pub fn new(pattern: &str, case_sensitive: bool) -> Result<RegexMatcher, regex::Error> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(!case_sensitive)
+            .build()?;
+
+        Ok(RegexMatcher { regex, unfollowed: None })
+    }
+
+    /// Builds one regex matching any of `patterns`, so several patterns are
+    /// still searched in a single pass.
+    pub fn alternation<S: AsRef<str>>(patterns: &[S], case_sensitive: bool) -> Result<RegexMatcher, regex::Error> {
+        RegexMatcher::new(&alternate(patterns), case_sensitive)
+    }
+
+    /// Builds a regex for `patterns` whose matches must be followed by the
+    /// end of the text or what ends a match at `boundary`.
+    ///
+    /// Checking that after matching would only see the alternative the regex
+    /// preferred, so `-w 'foo|foobar'` wouldn't match `foobar`. As part of
+    /// the regex, every alternative is tried. The check comes last in its
+    /// own group, leaving the groups of the patterns numbered as written.
+    pub fn followed_by<S: AsRef<str>>(
+        patterns: &[S],
+        case_sensitive: bool,
+        boundary: Boundary,
+    ) -> Result<RegexMatcher, regex::Error> {
+        let follow = match boundary {
+            Boundary::Word => r"[^\p{Alphabetic}\p{N}_]",
+            Boundary::Line => r"[\r\n]",
+        };
+        let pattern = format!("(?:{})($|{})", alternate(patterns), follow);
+
+        let unfollowed = RegexMatcher::new(&format!(r"(?:{})\z", alternate(patterns)), case_sensitive)?;
+        let mut matcher = RegexMatcher::new(&pattern, case_sensitive)?;
+        matcher.unfollowed = Some(unfollowed.regex);
+        Ok(matcher)
+    }
+}
+
+fn alternate<S: AsRef<str>>(patterns: &[S]) -> String {
+    patterns.iter()
+        .map(|pattern| format!("(?:{})", pattern.as_ref()))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+impl Matcher for RegexMatcher {
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        if self.unfollowed.is_none() {
+            return self.regex.find_at(line, start).map(|m| m.range());
+        }
+
+        // The match ends where the text following it starts.
+        let captures = self.regex.captures_at(line, start)?;
+        let follow = captures.get(self.regex.captures_len() - 1)?;
+        Some(captures.get(0)?.start()..follow.start())
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        self.regex.is_match(line)
+    }
+
+    /// Expands `$1` and `${name}` references to the groups of the match.
+    fn expand(&self, line: &str, range: Range<usize>, replacement: &str) -> String {
+        let captures = match &self.unfollowed {
+            // Ending the text with the match keeps what follows out of `$0`.
+            Some(regex) => regex.captures_at(&line[..range.end], range.start),
+            None => self.regex.captures_at(line, range.start),
+        };
+
+        let mut expanded = String::new();
+        if let Some(captures) = captures {
+            captures.expand(replacement, &mut expanded);
+        }
+        expanded
+    }
+}
+
+/// A boundary a match must respect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Boundary {
+    /// The match is not preceded or followed by a word character (`-w`).
+    Word,
+    /// The match is the whole line (`-x`). When the text holds several
+    /// lines, the match must start and end at line boundaries.
+    Line,
+}
+
+/// Restricts another matcher to matches at a `Boundary`.
+pub struct Bounded {
+    inner: Box<dyn Matcher>,
+    boundary: Boundary,
+}
+
+impl Bounded {
+    pub fn new(inner: Box<dyn Matcher>, boundary: Boundary) -> Bounded {
+        Bounded { inner, boundary }
+    }
+
+    /// Returns the first match of `find` at or after `start` that respects
+    /// the boundary.
+    fn find_with<F: Fn(usize) -> Option<Range<usize>>>(&self, line: &str, start: usize, find: F) -> Option<Range<usize>> {
+        let mut from = start;
+
+        while from <= line.len() {
+            let range = find(from)?;
+            if self.accepts(line, &range) {
+                return Some(range);
+            }
+            if let Some(shorter) = self.inner.shorter_matches(line, range.clone())
+                .into_iter()
+                .find(|shorter| self.accepts(line, shorter))
+            {
+                return Some(shorter);
+            }
+            // Look for a later match starting inside the rejected one.
+            from = range.start + line[range.start..].chars().next().map_or(1, char::len_utf8);
+        }
+
+        None
+    }
+
+    fn accepts(&self, line: &str, range: &Range<usize>) -> bool {
+        match self.boundary {
+            Boundary::Word => {
+                !line[..range.start].chars().next_back().is_some_and(is_word_char)
+                    && !line[range.end..].chars().next().is_some_and(is_word_char)
+            }
+            Boundary::Line => {
+                (range.start == 0 || line[..range.start].ends_with('\n'))
+                    && (range.end == line.len() || line[range.end..].starts_with(['\n', '\r']))
+            }
+        }
+    }
+}
+
+impl Matcher for Bounded {
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        self.find_with(line, start, |from| self.inner.find_at(line, from))
+    }
+
+    fn finder<'t>(&'t self, text: &'t str) -> Finder<'t> {
+        let find = self.inner.finder(text);
+        Box::new(move |start| self.find_with(text, start, &find))
+    }
+
+    fn distance(&self, line: &str) -> Option<usize> {
+        self.inner.distance(line)
+    }
+
+    fn expand(&self, line: &str, range: Range<usize>, replacement: &str) -> String {
+        self.inner.expand(line, range, replacement)
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Builds the matcher for `patterns`: a regex or plain substring search,
+/// optionally restricted to a boundary. A line matches when any pattern
+/// does. With `multiline`, `^` and `$` in regexes match at every line of
+/// the text rather than only at its ends, before `\r\n` as well as `\n`.
+pub fn build<S: AsRef<str>>(
+    patterns: &[S],
+    regex: bool,
+    case_sensitive: bool,
+    boundary: Option<Boundary>,
+    multiline: bool,
+) -> Result<Box<dyn Matcher>, MinigrepError> {
+    if regex && multiline {
+        let patterns: Vec<String> = patterns.iter().map(|pattern| format!("(?mR){}", pattern.as_ref())).collect();
+        return build(&patterns, true, case_sensitive, boundary, false);
+    }
+
+    let matcher: Box<dyn Matcher> = match (regex, patterns, boundary) {
+        // Bounded still checks what precedes the match.
+        (true, patterns, Some(boundary)) if !patterns.is_empty() => {
+            Box::new(RegexMatcher::followed_by(patterns, case_sensitive, boundary)?)
+        }
+        (true, [pattern], None) => Box::new(RegexMatcher::new(pattern.as_ref(), case_sensitive)?),
+        (true, patterns, None) if !patterns.is_empty() => {
+            Box::new(RegexMatcher::alternation(patterns, case_sensitive)?)
+        }
+        (false, [pattern], _) => Box::new(Substring::new(pattern.as_ref(), case_sensitive)),
+        (_, patterns, None) => Box::new(MultiSubstring::new(patterns, case_sensitive)?),
+        (_, patterns, Some(_)) => Box::new(MultiSubstring::with_shorter_matches(patterns, case_sensitive)?),
+    };
+
+    Ok(match boundary {
+        Some(boundary) => Box::new(Bounded::new(matcher, boundary)),
+        None => matcher,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn substring_find() {
+        let matcher = Substring::new("duct", true);
+
+        assert_eq!(Some(15..19), matcher.find("safe, fast, productive."));
+        assert_eq!(None, matcher.find("Duct tape."));
+    }
+
+    #[test]
+    fn substring_case_insensitive_offsets() {
+        let matcher = Substring::new("STRASSE", false);
+        assert_eq!(Some(4..11), matcher.find("Die Straße ist lang"));
+
+        // İ lowercases to two characters, which used to shift later offsets.
+        let line = "İSTANBUL'da frog";
+        let matcher = Substring::new("FROG", false);
+        assert_eq!(Some(13..17), matcher.find(line));
+        assert_eq!("frog", &line[13..17]);
+    }
+
+    #[test]
+    fn substring_case_insensitive_non_ascii() {
+        let matcher = Substring::new("ΣΊΣΥΦΟΣ", false);
+        assert!(matcher.is_match("ο σίσυφος"));
+
+        let matcher = Substring::new("ǆ", false);
+        assert_eq!(vec![0..2, 3..5], matcher.find_all("Ǆ ǅ"));
+    }
+
+    #[test]
+    fn substring_find_all() {
+        let matcher = Substring::new("ab", true);
+
+        assert_eq!(vec![0..2, 3..5], matcher.find_all("ab ab a"));
+    }
+
+    #[test]
+    fn regex_find_all_skips_empty_matches() {
+        let matcher = RegexMatcher::new("x*", true).unwrap();
+
+        assert_eq!(vec![1..3], matcher.find_all("axxé"));
+    }
+
+    #[test]
+    fn multi_substring() {
+        let matcher = MultiSubstring::new(&["run", "Config", "config"], true).unwrap();
+
+        assert!(matcher.is_match("pub fn run(config: Config)"));
+        assert!(!matcher.is_match("fn main() {"));
+        assert_eq!(vec![7..10, 11..17, 19..25], matcher.find_all("pub fn run(config: Config)"));
+    }
+
+    #[test]
+    fn multi_substring_prefers_longest() {
+        let matcher = MultiSubstring::new(&["search", "search_with"], true).unwrap();
+
+        assert_eq!(Some(0..11), matcher.find("search_with(&matcher)"));
+    }
+
+    #[test]
+    fn multi_substring_case_insensitive() {
+        let matcher = MultiSubstring::new(&["STRASSE", "frog"], false).unwrap();
+
+        assert_eq!(vec![4..11, 12..16], matcher.find_all("Die Straße Frog"));
+    }
+
+    #[test]
+    fn multi_substring_without_patterns() {
+        let matcher = MultiSubstring::new::<&str>(&[], true).unwrap();
+
+        assert!(!matcher.is_match("anything"));
+    }
+
+    #[test]
+    fn regex_alternation() {
+        let matcher = RegexMatcher::alternation(&[r"^fn \w+", "^use"], true).unwrap();
+
+        assert!(matcher.is_match("fn main() {"));
+        assert!(matcher.is_match("use std::env;"));
+        assert!(!matcher.is_match("pub fn run() {"));
+    }
+
+    #[test]
+    fn whole_word() {
+        let matcher = Bounded::new(Box::new(Substring::new("duct", true)), Boundary::Word);
+
+        assert!(!matcher.is_match("safe, fast, productive."));
+        assert_eq!(Some(15..19), matcher.find("productive and duct tape"));
+        assert_eq!(Some(11..15), matcher.find("été_duct duct!"));
+        assert!(!matcher.is_match("éduct"));
+    }
+
+    #[test]
+    fn whole_word_tries_shorter_patterns() {
+        let matcher = build(&["foo", "foo-bar"], false, true, Some(Boundary::Word), false).unwrap();
+
+        assert_eq!(Some(0..3), matcher.find("foo-barx"));
+        assert_eq!(vec![0..7, 8..11], matcher.find_all("foo-bar foo-barx"));
+
+        let matcher = build(&["STRASSE", "STRASSE-NORD"], false, false, Some(Boundary::Word), false).unwrap();
+        assert_eq!(Some(0..7), matcher.find("Straße-Nordost"));
+        assert_eq!(None, build(&["a", "ab"], false, true, Some(Boundary::Line), false).unwrap().find("abc"));
+    }
+
+    #[test]
+    fn whole_line() {
+        let matcher = Bounded::new(Box::new(Substring::new("pick three.", false)), Boundary::Line);
+
+        assert!(matcher.is_match("Pick three."));
+        assert!(!matcher.is_match("Pick three. Or four."));
+        assert_eq!(Some(9..20), matcher.find("Or four?\npick three.\r\nOr five."));
+    }
+
+    #[test]
+    fn bounded_regex_tries_every_alternative() {
+        let matcher = build(&["foo|foobar"], true, true, Some(Boundary::Word), false).unwrap();
+
+        assert_eq!(Some(0..6), matcher.find("foobar"));
+        assert_eq!(vec![0..3, 4..10], matcher.find_all("foo foobar foobarx xfoo"));
+
+        let matcher = build(&["a|abc"], true, true, Some(Boundary::Line), false).unwrap();
+        assert_eq!(Some(0..3), matcher.find("abc"));
+        assert_eq!(Some(2..5), matcher.find("x\nabc\r\n"));
+    }
+
+    #[test]
+    fn bounded_regex_keeps_group_numbers() {
+        let matcher = build(&[r"(\w+)@(\w+)"], true, true, Some(Boundary::Word), false).unwrap();
+        let line = "mail ann@example now";
+
+        let range = matcher.find(line).unwrap();
+        assert_eq!(5..16, range);
+        assert_eq!("example:ann", matcher.expand(line, range, "$2:$1"));
+    }
+
+    #[test]
+    fn bounded_regex_expands_only_the_match() {
+        let matcher = build(&["foo|foobar"], true, true, Some(Boundary::Word), false).unwrap();
+        let line = "foo foobar.";
+
+        assert_eq!(
+            vec!["[foo]", "[foobar]"],
+            matcher.find_all(line).into_iter().map(|range| matcher.expand(line, range, "[$0]")).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn regex_anchored() {
+        let matcher = RegexMatcher::new(r"^fn \w+", true).unwrap();
+
+        assert!(matcher.is_match("fn main() {"));
+        assert!(!matcher.is_match("pub fn run() {"));
+        assert_eq!(Some(0..7), matcher.find("fn main() {"));
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        let matcher = RegexMatcher::new("rust", false).unwrap();
+
+        assert!(matcher.is_match("Trust me."));
+    }
+
+    #[test]
+    fn regex_invalid_pattern() {
+        assert!(RegexMatcher::new("(", true).is_err());
+    }
+}
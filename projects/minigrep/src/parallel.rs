@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::error::MinigrepError;
+use crate::printer::Printer;
+use crate::searcher::Searcher;
+
+/// Searches `files` with `threads` workers and prints each file's output in
+/// the order the files were given, whichever worker finishes first.
+///
+/// `on_file` is called in order with each file and its number of selected
+/// lines, or the error searching it. It returns `false` to stop the search
+/// early, or an error to abort it.
+pub fn search_files<W, F>(
+    files: &[PathBuf],
+    searcher: &Searcher,
+    printer: &mut Printer<W>,
+    threads: usize,
+    mut on_file: F,
+) -> Result<(), MinigrepError>
+where
+    W: Write,
+    F: FnMut(&Path, Result<u64, MinigrepError>) -> Result<bool, MinigrepError>,
+{
+    if threads <= 1 || files.len() <= 1 {
+        for path in files {
+            let count = searcher.search_path(path, printer).map(|stats| stats.matches);
+            if !on_file(path, count)? {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    let template = printer.buffered();
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+
+        for _ in 0..threads.min(files.len()) {
+            let tx = tx.clone();
+            let (template, next, stop) = (&template, &next, &stop);
+
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let path = match files.get(index) {
+                        Some(path) => path,
+                        None => break,
+                    };
+
+                    let mut buffered = template.buffered();
+                    let count = searcher.search_path(path, &mut buffered).map(|stats| stats.matches);
+                    if tx.send((index, buffered, count)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Results that arrived before the files preceding them.
+        let mut pending = BTreeMap::new();
+        let mut next_output = 0;
+
+        for (index, buffered, count) in rx {
+            pending.insert(index, (buffered, count));
+
+            while let Some((buffered, count)) = pending.remove(&next_output) {
+                let path = &files[next_output];
+                next_output += 1;
+
+                let keep_going = printer.append(buffered)
+                    .map_err(MinigrepError::Output)
+                    .and_then(|()| on_file(path, count))
+                    .inspect_err(|_| stop.store(true, Ordering::Relaxed))?;
+
+                if !keep_going {
+                    stop.store(true, Ordering::Relaxed);
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn output_follows_file_order() {
+        let root = env::temp_dir().join(format!("minigrep-parallel-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+
+        let files: Vec<PathBuf> = (0..20)
+            .map(|i| {
+                let path = root.join(format!("{:02}.txt", i));
+                let contents = "no\nmatch\n".repeat(if i % 3 == 0 { 0 } else { 50 * i });
+                fs::write(&path, contents).unwrap();
+                path
+            })
+            .collect();
+
+        let searcher = Searcher::builder().pattern("match").after_context(1).build().unwrap();
+        let mut sequential = Printer::new(Vec::new());
+        sequential.with_filename = true;
+        let mut parallel = sequential.buffered();
+
+        let mut sequential_counts = Vec::new();
+        search_files(&files, &searcher, &mut sequential, 1, |_, count| {
+            sequential_counts.push(count.unwrap());
+            Ok(true)
+        }).unwrap();
+
+        let mut parallel_counts = Vec::new();
+        search_files(&files, &searcher, &mut parallel, 4, |_, count| {
+            parallel_counts.push(count.unwrap());
+            Ok(true)
+        }).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(sequential_counts, parallel_counts);
+        assert_eq!(sequential.into_inner(), parallel.into_inner());
+    }
+}
@@ -0,0 +1,567 @@
+use std::borrow::Cow;
+use std::io::{self, IsTerminal, Write};
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::json;
+use crate::matcher::Matcher;
+use crate::replace;
+use crate::searcher::{SearchStats, Searcher, Sink};
+use crate::{trim_newline, Match};
+
+/// What gets printed for each searched file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputMode {
+    /// Print the selected lines (the default).
+    Lines,
+    /// Print the number of selected lines (`-c`).
+    Count,
+    /// Print the names of files with a selected line (`-l`).
+    FilesWithMatches,
+    /// Print the names of files without a selected line (`-L`).
+    FilesWithoutMatches,
+    /// Print nothing and stop at the first selected line (`-q`).
+    Quiet,
+    /// Print JSON Lines records, see the `json` module (`--json`).
+    Json,
+}
+
+/// When to highlight output with ANSI colors (`--color`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorChoice {
+    /// Color only when standard output is a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Resolves `Auto` against whether standard output is a terminal.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stdout().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const PATH_COLOR: &str = "\x1b[35m";
+const LINE_NUMBER_COLOR: &str = "\x1b[32m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Writes search results, optionally with line numbers and context lines.
+///
+/// A `Printer` is the `Sink` used by the command line; the searcher decides
+/// which lines are selected and which are context.
+pub struct Printer<W: Write> {
+    out: W,
+    pub mode: OutputMode,
+    pub with_filename: bool,
+    pub line_number: bool,
+    pub color: bool,
+    /// Print selected lines with their matches replaced (`--replace`).
+    pub replacement: Option<String>,
+    /// Truncate lines longer than this many characters, except in JSON.
+    pub max_columns: Option<usize>,
+    /// Whether the current searcher reports context lines.
+    context: bool,
+    path: PathBuf,
+    printed_group: bool,
+    printed_in_file: bool,
+    file_begun: bool,
+    stats: Stats,
+}
+
+/// Totals over every file searched, reported by the JSON summary record.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub files: u64,
+    pub files_with_matches: u64,
+    pub matches: u64,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(out: W) -> Printer<W> {
+        Printer {
+            out,
+            mode: OutputMode::Lines,
+            with_filename: false,
+            line_number: false,
+            color: false,
+            replacement: None,
+            max_columns: None,
+            context: false,
+            path: PathBuf::new(),
+            printed_group: false,
+            printed_in_file: false,
+            file_begun: false,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Returns a printer with the same settings that writes into a buffer, so
+    /// a file can be searched on another thread and its output appended later.
+    pub fn buffered(&self) -> Printer<Vec<u8>> {
+        let mut printer = Printer::new(Vec::new());
+        printer.mode = self.mode;
+        printer.with_filename = self.with_filename;
+        printer.line_number = self.line_number;
+        printer.color = self.color;
+        printer.replacement = self.replacement.clone();
+        printer.max_columns = self.max_columns;
+        printer
+    }
+
+    /// Writes the output of a buffered printer, separating its first context
+    /// group from earlier ones as a single printer would have.
+    pub fn append(&mut self, other: Printer<Vec<u8>>) -> io::Result<()> {
+        if other.printed_group {
+            if other.context && self.printed_group && self.mode != OutputMode::Json {
+                self.write_separator("--")?;
+                writeln!(self.out)?;
+            }
+            self.printed_group = true;
+        }
+
+        self.stats.files += other.stats.files;
+        self.stats.files_with_matches += other.stats.files_with_matches;
+        self.stats.matches += other.stats.matches;
+
+        self.out.write_all(&other.out)
+    }
+
+    /// Returns the totals of the files searched so far.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Flushes the output written so far, for searches that don't end.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Writes whatever closes the output once every file has been searched.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.mode == OutputMode::Json {
+            json::write_summary(&mut self.out, &self.stats)?;
+        }
+        self.out.flush()
+    }
+
+    fn prints_lines(&self) -> bool {
+        matches!(self.mode, OutputMode::Lines | OutputMode::Json)
+    }
+
+    /// Separates the first group of a file from the previous file's output.
+    fn start_group(&mut self) -> io::Result<()> {
+        if !self.printed_in_file {
+            if self.context && self.printed_group && self.mode != OutputMode::Json {
+                self.write_separator("--")?;
+                writeln!(self.out)?;
+            }
+            self.printed_in_file = true;
+            self.printed_group = true;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, m: &Match, sep: char, highlight: Option<&dyn Matcher>) -> io::Result<()> {
+        // Borrow the path out of `self` while the line is written.
+        let path = mem::take(&mut self.path);
+        let result = self.write_line_in(&path, m, sep, highlight);
+        self.path = path;
+        result
+    }
+
+    fn write_line_in(&mut self, path: &Path, m: &Match, sep: char, highlight: Option<&dyn Matcher>) -> io::Result<()> {
+        let (line_number, offset, line) = (m.line_number, m.offset, m.line);
+        let json = self.mode == OutputMode::Json;
+        let (line, ranges) = match (highlight, &self.replacement) {
+            (Some(matcher), Some(replacement)) => {
+                let (replaced, ranges) = replace::replace_line(matcher, line, replacement);
+                (Cow::Owned(replaced), ranges)
+            }
+            (Some(matcher), None) if self.color || json => (Cow::Borrowed(line), matcher.find_all(line)),
+            _ => (Cow::Borrowed(line), Vec::new()),
+        };
+
+        if json {
+            if !self.file_begun {
+                json::write_begin(&mut self.out, path)?;
+                self.file_begun = true;
+            }
+
+            let record = if sep == ':' { "match" } else { "context" };
+            return json::write_line(&mut self.out, record, path, line_number, offset, &line, &ranges);
+        }
+
+        // A multiline match spans several lines, each printed with its own
+        // prefix and the highlighted ranges that fall on it.
+        let mut start = 0;
+        for (i, segment) in line.split('\n').enumerate() {
+            let text = trim_newline(segment);
+            let end = start + text.len();
+            let clipped: Vec<Range<usize>> = ranges.iter()
+                .filter(|range| range.start < end && range.end > start)
+                .map(|range| range.start.max(start) - start..range.end.min(end) - start)
+                .collect();
+
+            self.write_text_line(path, line_number + i, sep, text, &clipped)?;
+            start += segment.len() + 1;
+        }
+
+        Ok(())
+    }
+
+    fn write_text_line(
+        &mut self,
+        path: &Path,
+        line_number: usize,
+        sep: char,
+        line: &str,
+        ranges: &[Range<usize>],
+    ) -> io::Result<()> {
+        let sep = sep.to_string();
+
+        if self.with_filename {
+            self.write_path(path)?;
+            self.write_separator(&sep)?;
+        }
+        if self.line_number {
+            self.write_colored(LINE_NUMBER_COLOR, &line_number.to_string())?;
+            self.write_separator(&sep)?;
+        }
+
+        // Cut long lines at a character boundary; matches past the cut
+        // aren't highlighted.
+        let (line, omitted) = match self.max_columns.and_then(|max| line.char_indices().nth(max)) {
+            Some((cut, _)) => (&line[..cut], line.len() - cut),
+            None => (line, 0),
+        };
+
+        if self.color {
+            let mut last = 0;
+            for range in ranges {
+                // Skip ranges that don't fall on character boundaries.
+                if range.start < last || line.get(range.clone()).is_none() {
+                    continue;
+                }
+                write!(self.out, "{}", &line[last..range.start])?;
+                self.write_colored(MATCH_COLOR, &line[range.clone()])?;
+                last = range.end;
+            }
+            write!(self.out, "{}", &line[last..])?;
+        } else {
+            write!(self.out, "{}", line)?;
+        }
+
+        if omitted > 0 {
+            write!(self.out, " [... {} more bytes]", omitted)?;
+        }
+        writeln!(self.out)
+    }
+
+    fn write_path(&mut self, path: &Path) -> io::Result<()> {
+        self.write_colored(PATH_COLOR, &path.display().to_string())
+    }
+
+    fn write_separator(&mut self, sep: &str) -> io::Result<()> {
+        self.write_colored(SEPARATOR_COLOR, sep)
+    }
+
+    fn write_colored(&mut self, color: &str, text: &str) -> io::Result<()> {
+        if self.color {
+            write!(self.out, "{}{}{}", color, text, RESET)
+        } else {
+            write!(self.out, "{}", text)
+        }
+    }
+}
+
+impl<W: Write> Sink for Printer<W> {
+    fn begin(&mut self, searcher: &Searcher, path: &Path) -> io::Result<()> {
+        self.stats.files += 1;
+        self.context = searcher.has_context();
+        self.path = path.to_path_buf();
+        self.printed_in_file = false;
+        self.file_begun = false;
+        Ok(())
+    }
+
+    /// Prints the line, or with a per-file mode, stops at the first one when
+    /// that is all the mode needs to know.
+    fn matched(&mut self, searcher: &Searcher, m: &Match) -> io::Result<bool> {
+        match self.mode {
+            OutputMode::Lines | OutputMode::Json => {
+                self.start_group()?;
+                let highlight = if searcher.invert_match() { None } else { Some(searcher.matcher()) };
+                self.write_line(m, ':', highlight)?;
+                Ok(true)
+            }
+            OutputMode::Count => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    fn context(&mut self, _searcher: &Searcher, m: &Match) -> io::Result<bool> {
+        if self.prints_lines() {
+            self.start_group()?;
+            self.write_line(m, '-', None)?;
+        }
+        Ok(true)
+    }
+
+    fn context_break(&mut self, _searcher: &Searcher) -> io::Result<bool> {
+        if self.mode == OutputMode::Lines {
+            self.write_separator("--")?;
+            writeln!(self.out)?;
+        }
+        Ok(true)
+    }
+
+    fn finish(&mut self, _searcher: &Searcher, path: &Path, stats: &SearchStats) -> io::Result<()> {
+        let count = stats.matches;
+        self.stats.matches += count;
+        if count > 0 {
+            self.stats.files_with_matches += 1;
+        }
+
+        // Binary input only gets a "matches" message instead of its lines.
+        if stats.binary && count > 0 && self.mode == OutputMode::Lines {
+            write!(self.out, "Binary file ")?;
+            self.write_path(path)?;
+            writeln!(self.out, " matches")?;
+        }
+
+        match self.mode {
+            OutputMode::Count => {
+                if self.with_filename {
+                    self.write_path(path)?;
+                    self.write_separator(":")?;
+                }
+                writeln!(self.out, "{}", count)?;
+            }
+            OutputMode::FilesWithMatches if count > 0 => {
+                self.write_path(path)?;
+                writeln!(self.out)?;
+            }
+            OutputMode::FilesWithoutMatches if count == 0 => {
+                self.write_path(path)?;
+                writeln!(self.out)?;
+            }
+            OutputMode::Json if self.file_begun => json::write_end(&mut self.out, path, count)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::searcher::{BinaryMode, SearcherBuilder};
+
+    const CONTENTS: &str = "\
+one
+two match
+three
+four
+five
+six match
+seven
+eight match
+nine";
+
+    fn searcher(builder: SearcherBuilder) -> Searcher {
+        builder.pattern("match").build().unwrap()
+    }
+
+    fn print(printer: &mut Printer<Vec<u8>>, searcher: &Searcher, path: &str, contents: &[u8]) -> u64 {
+        searcher.search_reader(Path::new(path), contents, printer).unwrap().matches
+    }
+
+    fn render(before: usize, after: usize) -> String {
+        let mut printer = Printer::new(Vec::new());
+        printer.line_number = true;
+
+        let searcher = searcher(Searcher::builder().before_context(before).after_context(after));
+        print(&mut printer, &searcher, "poem.txt", CONTENTS.as_bytes());
+        String::from_utf8(printer.out).unwrap()
+    }
+
+    #[test]
+    fn line_numbers() {
+        assert_eq!("2:two match\n6:six match\n8:eight match\n", render(0, 0));
+    }
+
+    #[test]
+    fn context_groups() {
+        assert_eq!(
+            "1-one\n2:two match\n3-three\n--\n5-five\n6:six match\n7-seven\n8:eight match\n9-nine\n",
+            render(1, 1)
+        );
+    }
+
+    #[test]
+    fn after_context_merges_adjacent_groups() {
+        assert_eq!(
+            "2:two match\n3-three\n4-four\n5-five\n6:six match\n7-seven\n8:eight match\n9-nine\n",
+            render(0, 3)
+        );
+    }
+
+    #[test]
+    fn separator_between_files() {
+        let mut printer = Printer::new(Vec::new());
+        printer.with_filename = true;
+
+        let searcher = searcher(Searcher::builder().after_context(1));
+        print(&mut printer, &searcher, "a", b"match\nend\n");
+        print(&mut printer, &searcher, "b", b"match\n");
+
+        assert_eq!("a:match\na-end\n--\nb:match\n", String::from_utf8(printer.out).unwrap());
+    }
+
+    #[test]
+    fn append_buffered_output() {
+        let mut printer = Printer::new(Vec::new());
+        printer.with_filename = true;
+
+        let searcher = searcher(Searcher::builder().after_context(1));
+        let mut a = printer.buffered();
+        print(&mut a, &searcher, "a", b"match\nend\n");
+        let mut b = printer.buffered();
+        print(&mut b, &searcher, "b", b"none\n");
+        let mut c = printer.buffered();
+        print(&mut c, &searcher, "c", b"match\n");
+
+        printer.append(a).unwrap();
+        printer.append(b).unwrap();
+        printer.append(c).unwrap();
+
+        assert_eq!("a:match\na-end\n--\nc:match\n", String::from_utf8(printer.out).unwrap());
+    }
+
+    #[test]
+    fn replacement_preview() {
+        let mut printer = Printer::new(Vec::new());
+        printer.line_number = true;
+        printer.replacement = Some(String::from("MATCH"));
+
+        print(&mut printer, &searcher(Searcher::builder()), "a", CONTENTS.as_bytes());
+
+        assert_eq!("2:two MATCH\n6:six MATCH\n8:eight MATCH\n", String::from_utf8(printer.out).unwrap());
+    }
+
+    #[test]
+    fn max_columns() {
+        let mut printer = Printer::new(Vec::new());
+        printer.max_columns = Some(8);
+        printer.color = true;
+
+        print(&mut printer, &searcher(Searcher::builder()), "a", "short match\nmatch été and more\n".as_bytes());
+
+        assert_eq!(
+            "short ma [... 3 more bytes]\n\x1b[1;31mmatch\x1b[0m ét [... 11 more bytes]\n",
+            String::from_utf8(printer.out).unwrap()
+        );
+    }
+
+    #[test]
+    fn multiline_blocks() {
+        let mut printer = Printer::new(Vec::new());
+        printer.color = true;
+        printer.line_number = true;
+
+        let searcher = Searcher::builder().pattern("o\r\nb").multiline(true).build().unwrap();
+        print(&mut printer, &searcher, "a", b"start\nfoo\r\nbar\nend\n");
+
+        assert_eq!(
+            "\x1b[32m2\x1b[0m\x1b[36m:\x1b[0mfo\x1b[1;31mo\x1b[0m\n\
+             \x1b[32m3\x1b[0m\x1b[36m:\x1b[0m\x1b[1;31mb\x1b[0mar\n",
+            String::from_utf8(printer.out).unwrap()
+        );
+    }
+
+    #[test]
+    fn binary_modes() {
+        let mut printer = Printer::new(Vec::new());
+        let binary = b"match\0\nmatch\n";
+
+        let text = searcher(Searcher::builder().binary(BinaryMode::Text));
+        assert_eq!(2, print(&mut printer, &text, "a", binary));
+        let skip = searcher(Searcher::builder().binary(BinaryMode::Skip));
+        assert_eq!(0, print(&mut printer, &skip, "a", binary));
+        let matches = searcher(Searcher::builder().binary(BinaryMode::Matches));
+        assert_eq!(2, print(&mut printer, &matches, "a", binary));
+
+        assert_eq!("match\0\nmatch\nBinary file a matches\n", String::from_utf8(printer.out).unwrap());
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let mut printer = Printer::new(Vec::new());
+        let searcher = Searcher::builder().pattern("caf").build().unwrap();
+
+        let count = print(&mut printer, &searcher, "a", b"caf\xe9\nok\n");
+
+        assert_eq!(1, count);
+        assert_eq!("caf\u{fffd}\n", String::from_utf8(printer.out).unwrap());
+    }
+
+    fn render_mode(mode: OutputMode, invert_match: bool) -> (String, u64) {
+        let mut printer = Printer::new(Vec::new());
+        printer.mode = mode;
+        printer.with_filename = true;
+
+        let searcher = searcher(Searcher::builder().invert_match(invert_match));
+        let count = print(&mut printer, &searcher, "poem.txt", CONTENTS.as_bytes());
+        (String::from_utf8(printer.out).unwrap(), count)
+    }
+
+    #[test]
+    fn output_modes() {
+        assert_eq!((String::from("poem.txt:3\n"), 3), render_mode(OutputMode::Count, false));
+        assert_eq!((String::from("poem.txt:6\n"), 6), render_mode(OutputMode::Count, true));
+        assert_eq!((String::from("poem.txt\n"), 1), render_mode(OutputMode::FilesWithMatches, false));
+        assert_eq!((String::new(), 1), render_mode(OutputMode::FilesWithoutMatches, false));
+        assert_eq!((String::new(), 1), render_mode(OutputMode::Quiet, false));
+    }
+
+    #[test]
+    fn colored_output() {
+        let mut printer = Printer::new(Vec::new());
+        printer.color = true;
+        printer.with_filename = true;
+        printer.line_number = true;
+
+        let searcher = Searcher::builder().pattern("o").after_context(1).build().unwrap();
+        print(&mut printer, &searcher, "a", b"foo\nbar\n");
+
+        assert_eq!(
+            "\x1b[35ma\x1b[0m\x1b[36m:\x1b[0m\x1b[32m1\x1b[0m\x1b[36m:\x1b[0m\
+             f\x1b[1;31mo\x1b[0m\x1b[1;31mo\x1b[0m\n\
+             \x1b[35ma\x1b[0m\x1b[36m-\x1b[0m\x1b[32m2\x1b[0m\x1b[36m-\x1b[0mbar\n",
+            String::from_utf8(printer.out).unwrap()
+        );
+    }
+
+    #[test]
+    fn invert_match() {
+        let (out, count) = render_mode(OutputMode::Lines, true);
+
+        assert_eq!(6, count);
+        assert!(out.starts_with("poem.txt:one\npoem.txt:three\n"));
+        assert!(!out.contains("match"));
+    }
+}
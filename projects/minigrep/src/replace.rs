@@ -0,0 +1,215 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::matcher::Matcher;
+use crate::trim_newline;
+
+/// Replaces every match of `matcher` in `line` with `replacement`, returning
+/// the new line and the ranges the replacements occupy in it.
+///
+/// Both the `--replace` preview and `--in-place` go through this function,
+/// so what is shown is exactly what gets written.
+pub fn replace_line(matcher: &dyn Matcher, line: &str, replacement: &str) -> (String, Vec<Range<usize>>) {
+    let mut replaced = String::with_capacity(line.len());
+    let mut ranges = Vec::new();
+    let mut last = 0;
+
+    for range in matcher.find_all(line) {
+        if range.start < last || line.get(range.clone()).is_none() {
+            continue;
+        }
+        replaced.push_str(&line[last..range.start]);
+
+        let start = replaced.len();
+        replaced.push_str(&matcher.expand(line, range.clone(), replacement));
+        ranges.push(start..replaced.len());
+
+        last = range.end;
+    }
+    replaced.push_str(&line[last..]);
+
+    (replaced, ranges)
+}
+
+/// Rewrites `path` with every match on the given lines replaced, returning
+/// the number of lines changed. `lines` holds the 1-based numbers of the
+/// lines the search selected, in order, so the rewrite changes exactly the
+/// lines the preview shows. Other lines are copied byte for byte. The file
+/// is left untouched when nothing changes.
+///
+/// The new contents are written to a temporary file next to `path`, which
+/// then replaces it with a rename, so the file is never seen half-written.
+/// When `backup_suffix` is given the original is first copied to `path`
+/// with the suffix appended. A symbolic link is resolved first, so the
+/// file it points to is rewritten and the link stays in place.
+pub fn rewrite_file(
+    path: &Path,
+    matcher: &dyn Matcher,
+    replacement: &str,
+    lines: &[usize],
+    backup_suffix: Option<&str>,
+) -> io::Result<u64> {
+    let path = &fs::canonicalize(path)?;
+    let temp = temp_path(path);
+    let result = write_replaced(path, &temp, matcher, replacement, lines);
+
+    let changed = match result {
+        Ok(changed) if changed > 0 => changed,
+        other => {
+            let _ = fs::remove_file(&temp);
+            return other;
+        }
+    };
+
+    let finish = || -> io::Result<()> {
+        if let Some(suffix) = backup_suffix {
+            let mut backup = path.as_os_str().to_os_string();
+            backup.push(suffix);
+            fs::copy(path, backup)?;
+        }
+        fs::rename(&temp, path)
+    };
+
+    finish()
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })
+        .map(|_| changed)
+}
+
+fn write_replaced(path: &Path, temp: &Path, matcher: &dyn Matcher, replacement: &str, lines: &[usize]) -> io::Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+    file.set_permissions(fs::metadata(path)?.permissions())?;
+    let mut writer = BufWriter::new(file);
+
+    let mut buf = Vec::new();
+    let mut selected = lines.iter().peekable();
+    let mut line_number = 0;
+    let mut changed = 0;
+
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        line_number += 1;
+
+        if selected.next_if_eq(&&line_number).is_none() {
+            writer.write_all(&buf)?;
+            continue;
+        }
+
+        let text = std::str::from_utf8(&buf).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {} isn't valid UTF-8", line_number))
+        })?;
+        let line = trim_newline(text);
+        let terminator = &text[line.len()..];
+
+        let (replaced, ranges) = replace_line(matcher, line, replacement);
+        if !ranges.is_empty() && replaced != line {
+            changed += 1;
+        }
+
+        writer.write_all(replaced.as_bytes())?;
+        writer.write_all(terminator.as_bytes())?;
+    }
+
+    writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+    Ok(changed)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map_or_else(Default::default, |name| name.to_string_lossy());
+    path.with_file_name(format!(".{}.minigrep-{}.tmp", name, process::id()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::matcher::{RegexMatcher, Substring};
+    use std::env;
+
+    #[test]
+    fn replaces_every_match() {
+        let matcher = Substring::new("frog", false);
+
+        assert_eq!(
+            (String::from("a toad, a toad"), vec![2..6, 10..14]),
+            replace_line(&matcher, "a Frog, a frog", "toad")
+        );
+    }
+
+    #[test]
+    fn expands_regex_captures() {
+        let matcher = RegexMatcher::new(r"fn (\w+)", true).unwrap();
+
+        let (replaced, ranges) = replace_line(&matcher, "pub fn run()", "fn ${1}_all");
+
+        assert_eq!("pub fn run_all()", replaced);
+        assert_eq!("fn run_all", &replaced[ranges[0].clone()]);
+    }
+
+    #[test]
+    fn rewrites_in_place_with_backup() {
+        let path = env::temp_dir().join(format!("minigrep-replace-{}.txt", process::id()));
+        fs::write(&path, "frog\r\npond\nfrog frog").unwrap();
+
+        let matcher = Substring::new("frog", true);
+        let changed = rewrite_file(&path, &matcher, "toad", &[1, 3], Some(".bak")).unwrap();
+
+        let backup = PathBuf::from(format!("{}.bak", path.display()));
+        let contents = fs::read_to_string(&path).unwrap();
+        let original = fs::read_to_string(&backup).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&backup).unwrap();
+
+        assert_eq!(2, changed);
+        assert_eq!("toad\r\npond\ntoad toad", contents);
+        assert_eq!("frog\r\npond\nfrog frog", original);
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn rewrites_only_selected_lines() {
+        let path = env::temp_dir().join(format!("minigrep-replace-selected-{}.txt", process::id()));
+        fs::write(&path, b"frog\n\xe9tang frog\nfrog\n").unwrap();
+
+        let matcher = Substring::new("frog", true);
+        let changed = rewrite_file(&path, &matcher, "toad", &[1], None).unwrap();
+        let contents = fs::read(&path).unwrap();
+        let invalid = rewrite_file(&path, &matcher, "toad", &[2], None);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(1, changed);
+        assert_eq!(b"toad\n\xe9tang frog\nfrog\n".to_vec(), contents);
+        assert_eq!(io::ErrorKind::InvalidData, invalid.unwrap_err().kind());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rewrites_through_symlinks() {
+        let path = env::temp_dir().join(format!("minigrep-replace-real-{}.txt", process::id()));
+        let link = env::temp_dir().join(format!("minigrep-replace-link-{}.txt", process::id()));
+        fs::write(&path, "frog\n").unwrap();
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+
+        let matcher = Substring::new("frog", true);
+        rewrite_file(&link, &matcher, "toad", &[1], Some(".bak")).unwrap();
+
+        let backup = PathBuf::from(format!("{}.bak", path.display()));
+        let is_symlink = fs::symlink_metadata(&link).unwrap().file_type().is_symlink();
+        let contents = fs::read_to_string(&path).unwrap();
+        let original = fs::read_to_string(&backup).unwrap();
+        for file in [&path, &link, &backup] {
+            fs::remove_file(file).unwrap();
+        }
+
+        assert!(is_symlink);
+        assert_eq!("toad\n", contents);
+        assert_eq!("frog\n", original);
+    }
+}
@@ -0,0 +1,801 @@
+//! Line-oriented searching that reports results to a `Sink`.
+//!
+//! `Searcher` does the reading, matching and context bookkeeping; what to
+//! do with the results is up to the `Sink`. The command line's `Printer` is
+//! one sink, and tools embedding minigrep can supply their own:
+//!
+//! ```
+//! use std::io;
+//! use std::path::Path;
+//!
+//! use minigrep::searcher::{Searcher, Sink};
+//! use minigrep::{CaseMode, Match};
+//!
+//! struct Collect(Vec<usize>);
+//!
+//! impl Sink for Collect {
+//!     fn matched(&mut self, _: &Searcher, m: &Match) -> io::Result<bool> {
+//!         self.0.push(m.line_number);
+//!         Ok(self.0.len() < 2) // stop after two matches
+//!     }
+//! }
+//!
+//! let searcher = Searcher::builder()
+//!     .pattern("rust")
+//!     .case(CaseMode::Insensitive)
+//!     .build()
+//!     .unwrap();
+//!
+//! let mut sink = Collect(Vec::new());
+//! let contents = "Rust:\nsafe, fast, productive.\nTrust me.\nrusty\n";
+//! searcher.search_reader(Path::new("poem"), contents.as_bytes(), &mut sink).unwrap();
+//!
+//! assert_eq!(vec![1, 3], sink.0);
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::error::MinigrepError;
+use crate::input::{self, InputOptions};
+use crate::matcher::{self, Boundary, Matcher};
+use crate::{trim_newline, CaseMode, Match};
+
+/// How many bytes at the start of an input are checked for a NUL byte, the
+/// sign of binary input. Memory-mapped input is available whole, but only
+/// this much of it is checked so every read strategy agrees.
+const BINARY_HEAD: usize = 8 * 1024;
+
+/// What to do with input that looks binary (`--binary`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryMode {
+    /// Don't search it.
+    Skip,
+    /// Search it as if it were text (`-a`).
+    Text,
+    /// Search it, but only report whether it matches instead of its lines.
+    Matches,
+}
+
+/// Receives the results of searching one input.
+///
+/// Every method except `matched` does nothing by default. Methods returning
+/// `Ok(false)` stop the search of the current input early; errors abort it
+/// and are returned as `MinigrepError::Output`.
+pub trait Sink {
+    /// Called before an input is searched.
+    fn begin(&mut self, _searcher: &Searcher, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called for each selected line.
+    fn matched(&mut self, searcher: &Searcher, m: &Match) -> io::Result<bool>;
+
+    /// Called for each context line around the selected ones.
+    fn context(&mut self, _searcher: &Searcher, _line: &Match) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Called between two groups of context lines that aren't adjacent.
+    fn context_break(&mut self, _searcher: &Searcher) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Called after an input has been searched, even when it stopped early.
+    fn finish(&mut self, _searcher: &Searcher, _path: &Path, _stats: &SearchStats) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What a search of one input found.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchStats {
+    /// Number of selected lines, including those of binary input that were
+    /// counted but not reported.
+    pub matches: u64,
+    /// Whether the input looked binary.
+    pub binary: bool,
+}
+
+/// Searches inputs line by line with a fixed set of options.
+///
+/// Only the current line and up to `before_context` previous lines are held
+/// in memory, so input of any size can be searched. Invalid UTF-8 is
+/// replaced with U+FFFD rather than failing the search.
+pub struct Searcher {
+    matcher: Box<dyn Matcher>,
+    invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    max_count: Option<u64>,
+    binary: BinaryMode,
+    input: InputOptions,
+    multiline: bool,
+    sort_by_distance: bool,
+}
+
+impl Searcher {
+    pub fn builder() -> SearcherBuilder {
+        SearcherBuilder::default()
+    }
+
+    pub fn matcher(&self) -> &dyn Matcher {
+        self.matcher.as_ref()
+    }
+
+    pub fn invert_match(&self) -> bool {
+        self.invert_match
+    }
+
+    /// Whether context lines are reported around selected lines.
+    pub fn has_context(&self) -> bool {
+        self.before_context > 0 || self.after_context > 0
+    }
+
+    /// Searches the file at `path`, or standard input for `-`, decompressing
+    /// and decoding it according to the input options.
+    pub fn search_path<S: Sink>(&self, path: &Path, sink: &mut S) -> Result<SearchStats, MinigrepError> {
+        let display = if path == Path::new("-") { Path::new("(standard input)") } else { path };
+        let reader = input::open(path, &self.input).map_err(|err| MinigrepError::read(display, err))?;
+
+        self.search_reader(display, reader, sink)
+    }
+
+    /// Searches `reader`, reporting it to `sink` under the name `path`.
+    /// Errors reading it are `Io` or `Decode` errors for `path`.
+    pub fn search_reader<R: BufRead, S: Sink>(
+        &self,
+        path: &Path,
+        reader: R,
+        sink: &mut S,
+    ) -> Result<SearchStats, MinigrepError> {
+        if self.sort_by_distance {
+            self.search(path, reader, &mut ByDistance { sink, selected: Vec::new() })
+        } else {
+            self.search(path, reader, sink)
+        }
+    }
+
+    fn search<R: BufRead, S: Sink>(
+        &self,
+        path: &Path,
+        mut reader: R,
+        sink: &mut S,
+    ) -> Result<SearchStats, MinigrepError> {
+        let read_error = |err| MinigrepError::read(path, err);
+        let mut stats = SearchStats::default();
+        sink.begin(self, path).map_err(MinigrepError::Output)?;
+
+        if self.binary != BinaryMode::Text {
+            let head = reader.fill_buf().map_err(read_error)?;
+            stats.binary = head[..head.len().min(BINARY_HEAD)].contains(&0);
+        }
+        if stats.binary {
+            if self.binary == BinaryMode::Matches {
+                stats.matches = self.count_matches(reader).map_err(read_error)?;
+            }
+            sink.finish(self, path, &stats).map_err(MinigrepError::Output)?;
+            return Ok(stats);
+        }
+
+        if self.multiline {
+            self.search_multiline(path, reader, sink, &mut stats)?;
+            sink.finish(self, path, &stats).map_err(MinigrepError::Output)?;
+            return Ok(stats);
+        }
+
+        let context = self.has_context();
+        let mut before: VecDeque<(usize, usize, String)> = VecDeque::with_capacity(self.before_context);
+        let mut after_remaining = 0;
+        let mut last_reported: Option<usize> = None;
+
+        let mut buf = Vec::new();
+        let mut line_number = 0;
+        let mut next_offset = 0;
+
+        loop {
+            let limit_reached = self.max_count.is_some_and(|max| stats.matches >= max);
+            if limit_reached && after_remaining == 0 {
+                break;
+            }
+
+            buf.clear();
+            let read = reader.read_until(b'\n', &mut buf).map_err(read_error)?;
+            if read == 0 {
+                break;
+            }
+            line_number += 1;
+            let offset = next_offset;
+            next_offset += read;
+            let text = String::from_utf8_lossy(&buf);
+            let line = trim_newline(&text);
+
+            if !limit_reached && self.matcher.is_match(line) != self.invert_match {
+                stats.matches += 1;
+
+                let first = before.front().map_or(line_number, |(n, _, _)| *n);
+                let gap = last_reported.is_some_and(|last| first > last + 1);
+                if context && gap && !sink.context_break(self).map_err(MinigrepError::Output)? {
+                    break;
+                }
+
+                let mut keep_going = true;
+                for (n, context_offset, context_line) in before.drain(..) {
+                    let m = Match { line_number: n, offset: context_offset, line: &context_line };
+                    keep_going = keep_going && sink.context(self, &m).map_err(MinigrepError::Output)?;
+                }
+                let m = Match { line_number, offset, line };
+                if !keep_going || !sink.matched(self, &m).map_err(MinigrepError::Output)? {
+                    break;
+                }
+
+                last_reported = Some(line_number);
+                after_remaining = self.after_context;
+            } else if after_remaining > 0 {
+                let m = Match { line_number, offset, line };
+                if !sink.context(self, &m).map_err(MinigrepError::Output)? {
+                    break;
+                }
+                last_reported = Some(line_number);
+                after_remaining -= 1;
+            } else if self.before_context > 0 {
+                if before.len() == self.before_context {
+                    before.pop_front();
+                }
+                before.push_back((line_number, offset, line.to_string()));
+            }
+        }
+
+        sink.finish(self, path, &stats).map_err(MinigrepError::Output)?;
+        Ok(stats)
+    }
+
+    /// Searches the whole of `reader` at once so matches can span lines.
+    /// Each match is reported as one block of the lines it touches; matches
+    /// sharing a line are merged into a single block.
+    fn search_multiline<R: BufRead, S: Sink>(
+        &self,
+        path: &Path,
+        mut reader: R,
+        sink: &mut S,
+        stats: &mut SearchStats,
+    ) -> Result<(), MinigrepError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(|err| MinigrepError::read(path, err))?;
+        let text = String::from_utf8_lossy(&bytes);
+        let lines = Lines::new(&text);
+
+        let blocks = if self.invert_match {
+            let mut selected = Vec::new();
+            let mut next = 0;
+            for (first, last) in self.blocks(&lines) {
+                selected.extend((next..first).map(|n| (n, n)));
+                next = last + 1;
+            }
+            selected.extend((next..lines.len()).map(|n| (n, n)));
+            selected
+        } else {
+            self.blocks(&lines)
+        };
+
+        let limit = self.max_count.map_or(usize::MAX, |max| usize::try_from(max).unwrap_or(usize::MAX));
+        // Lines before this one have been reported or skipped.
+        let mut reported_to = 0;
+        let mut previous: Option<usize> = None;
+
+        for &(first, last) in blocks.iter().take(limit) {
+            if let Some(previous) = previous {
+                let after_end = (previous + 1 + self.after_context).min(first);
+                if !self.report_context(&lines, reported_to..after_end, sink)? {
+                    return Ok(());
+                }
+                reported_to = reported_to.max(after_end);
+            }
+
+            let before_start = first.saturating_sub(self.before_context).max(reported_to);
+            if self.has_context() && previous.is_some() && before_start > reported_to
+                && !sink.context_break(self).map_err(MinigrepError::Output)?
+            {
+                return Ok(());
+            }
+            if !self.report_context(&lines, before_start..first, sink)? {
+                return Ok(());
+            }
+
+            stats.matches += 1;
+            let m = Match { line_number: first + 1, offset: lines.start(first), line: lines.text(first..last + 1) };
+            if !sink.matched(self, &m).map_err(MinigrepError::Output)? {
+                return Ok(());
+            }
+            reported_to = last + 1;
+            previous = Some(last);
+        }
+
+        if let Some(previous) = previous {
+            let after_end = (previous + 1 + self.after_context).min(lines.len());
+            self.report_context(&lines, reported_to..after_end, sink)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the first and last line of each match, merging matches that
+    /// share a line.
+    fn blocks(&self, lines: &Lines) -> Vec<(usize, usize)> {
+        let text = lines.text;
+        let find = self.matcher.finder(text);
+        let mut blocks: Vec<(usize, usize)> = Vec::new();
+        let mut start = 0;
+
+        while start <= text.len() {
+            let range = match find(start) {
+                Some(range) => range,
+                None => break,
+            };
+
+            let first = lines.line_of(range.start);
+            let last = if range.is_empty() { first } else { lines.line_of(range.end - 1) };
+            start = if range.is_empty() {
+                range.end + text[range.end..].chars().next().map_or(1, char::len_utf8)
+            } else {
+                range.end
+            };
+            if first >= lines.len() {
+                // An empty match after the final line terminator.
+                break;
+            }
+
+            match blocks.last_mut() {
+                Some((_, previous_last)) if first <= *previous_last => *previous_last = last.max(*previous_last),
+                _ => blocks.push((first, last)),
+            }
+        }
+
+        blocks
+    }
+
+    fn report_context<S: Sink>(&self, lines: &Lines, range: Range<usize>, sink: &mut S) -> Result<bool, MinigrepError> {
+        for n in range {
+            let m = Match { line_number: n + 1, offset: lines.start(n), line: lines.text(n..n + 1) };
+            if !sink.context(self, &m).map_err(MinigrepError::Output)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Counts the selected lines of binary input without reporting them.
+    fn count_matches<R: BufRead>(&self, mut reader: R) -> io::Result<u64> {
+        let mut buf = Vec::new();
+        let mut count = 0;
+
+        while self.max_count.is_none_or(|max| count < max) && reader.read_until(b'\n', &mut buf)? > 0 {
+            let text = String::from_utf8_lossy(&buf);
+            if self.matcher.is_match(trim_newline(&text)) != self.invert_match {
+                count += 1;
+            }
+            buf.clear();
+        }
+
+        Ok(count)
+    }
+}
+
+/// Holds back the selected lines of an input and passes them on ordered by
+/// the matcher's distance once the input is done (`--sort-by-distance`).
+/// Lines at the same distance keep their order.
+struct ByDistance<'a, S> {
+    sink: &'a mut S,
+    selected: Vec<(usize, usize, String)>,
+}
+
+impl<S: Sink> Sink for ByDistance<'_, S> {
+    fn begin(&mut self, searcher: &Searcher, path: &Path) -> io::Result<()> {
+        self.sink.begin(searcher, path)
+    }
+
+    fn matched(&mut self, _: &Searcher, m: &Match) -> io::Result<bool> {
+        self.selected.push((m.line_number, m.offset, m.line.to_string()));
+        Ok(true)
+    }
+
+    fn context(&mut self, searcher: &Searcher, line: &Match) -> io::Result<bool> {
+        self.sink.context(searcher, line)
+    }
+
+    fn context_break(&mut self, searcher: &Searcher) -> io::Result<bool> {
+        self.sink.context_break(searcher)
+    }
+
+    fn finish(&mut self, searcher: &Searcher, path: &Path, stats: &SearchStats) -> io::Result<()> {
+        let mut selected = mem::take(&mut self.selected);
+        selected.sort_by_cached_key(|(_, _, line)| searcher.matcher().distance(line));
+
+        for (line_number, offset, line) in &selected {
+            let m = Match { line_number: *line_number, offset: *offset, line };
+            if !self.sink.matched(searcher, &m)? {
+                break;
+            }
+        }
+        self.sink.finish(searcher, path, stats)
+    }
+}
+
+/// The lines of a whole input held in memory.
+struct Lines<'a> {
+    text: &'a str,
+    /// Offset of the start of each line, followed by the end of the text.
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Lines<'a> {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        if starts.last() != Some(&text.len()) {
+            starts.push(text.len());
+        }
+        Lines { text, starts }
+    }
+
+    fn len(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    fn start(&self, line: usize) -> usize {
+        self.starts[line]
+    }
+
+    /// Returns the 0-based line containing `offset`.
+    fn line_of(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// Returns the given lines without the final line terminator.
+    fn text(&self, lines: Range<usize>) -> &'a str {
+        trim_newline(&self.text[self.starts[lines.start]..self.starts[lines.end]])
+    }
+}
+
+/// Configures a `Searcher`.
+///
+/// The matcher is either given directly with `matcher`, or built from the
+/// patterns, `regex`, `case` and `boundary` settings.
+pub struct SearcherBuilder {
+    matcher: Option<Box<dyn Matcher>>,
+    patterns: Vec<String>,
+    regex: bool,
+    case: CaseMode,
+    boundary: Option<Boundary>,
+    invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    max_count: Option<u64>,
+    binary: BinaryMode,
+    input: InputOptions,
+    multiline: bool,
+    sort_by_distance: bool,
+}
+
+impl Default for SearcherBuilder {
+    fn default() -> SearcherBuilder {
+        SearcherBuilder {
+            matcher: None,
+            patterns: Vec::new(),
+            regex: false,
+            case: CaseMode::Sensitive,
+            boundary: None,
+            invert_match: false,
+            before_context: 0,
+            after_context: 0,
+            max_count: None,
+            binary: BinaryMode::Text,
+            input: InputOptions::default(),
+            multiline: false,
+            sort_by_distance: false,
+        }
+    }
+}
+
+impl SearcherBuilder {
+    pub fn build(self) -> Result<Searcher, MinigrepError> {
+        let matcher = match self.matcher {
+            Some(matcher) => matcher,
+            None => {
+                let case_sensitive = self.case.is_sensitive(&self.patterns);
+                matcher::build(&self.patterns, self.regex, case_sensitive, self.boundary, self.multiline)?
+            }
+        };
+
+        Ok(Searcher {
+            matcher,
+            invert_match: self.invert_match,
+            before_context: self.before_context,
+            after_context: self.after_context,
+            max_count: self.max_count,
+            binary: self.binary,
+            input: self.input,
+            multiline: self.multiline,
+            sort_by_distance: self.sort_by_distance,
+        })
+    }
+
+    /// Uses `matcher` instead of building one from patterns.
+    pub fn matcher(mut self, matcher: Box<dyn Matcher>) -> SearcherBuilder {
+        self.matcher = Some(matcher);
+        self
+    }
+
+    /// Adds a pattern; a line is selected when any pattern matches.
+    pub fn pattern(mut self, pattern: &str) -> SearcherBuilder {
+        self.patterns.push(pattern.to_string());
+        self
+    }
+
+    pub fn patterns<I: IntoIterator<Item = S>, S: Into<String>>(mut self, patterns: I) -> SearcherBuilder {
+        self.patterns.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Treats the patterns as regular expressions.
+    pub fn regex(mut self, yes: bool) -> SearcherBuilder {
+        self.regex = yes;
+        self
+    }
+
+    pub fn case(mut self, case: CaseMode) -> SearcherBuilder {
+        self.case = case;
+        self
+    }
+
+    /// Only accepts matches at the given boundary.
+    pub fn boundary(mut self, boundary: Option<Boundary>) -> SearcherBuilder {
+        self.boundary = boundary;
+        self
+    }
+
+    /// Selects the lines that don't match instead.
+    pub fn invert_match(mut self, yes: bool) -> SearcherBuilder {
+        self.invert_match = yes;
+        self
+    }
+
+    pub fn before_context(mut self, lines: usize) -> SearcherBuilder {
+        self.before_context = lines;
+        self
+    }
+
+    pub fn after_context(mut self, lines: usize) -> SearcherBuilder {
+        self.after_context = lines;
+        self
+    }
+
+    /// Stops searching an input after this many selected lines, once the
+    /// trailing context of the last one has been reported.
+    pub fn max_count(mut self, max: Option<u64>) -> SearcherBuilder {
+        self.max_count = max;
+        self
+    }
+
+    pub fn binary(mut self, binary: BinaryMode) -> SearcherBuilder {
+        self.binary = binary;
+        self
+    }
+
+    pub fn input(mut self, input: InputOptions) -> SearcherBuilder {
+        self.input = input;
+        self
+    }
+
+    /// Lets matches span lines (`-U`). Each input is then read into memory
+    /// as a whole, and each match is reported as one `Match` holding every
+    /// line it touches, joined by their line terminators.
+    pub fn multiline(mut self, yes: bool) -> SearcherBuilder {
+        self.multiline = yes;
+        self
+    }
+
+    /// Reports the selected lines of each input ordered by how close they
+    /// are to the query, for approximate matchers such as `fuzzy::Fuzzy`.
+    /// Each input's selected lines are held in memory until it's done.
+    pub fn sort_by_distance(mut self, yes: bool) -> SearcherBuilder {
+        self.sort_by_distance = yes;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fuzzy::Fuzzy;
+
+    /// Records every event as a line of text.
+    #[derive(Default)]
+    struct Events {
+        events: Vec<String>,
+        stop_after: Option<usize>,
+    }
+
+    impl Sink for Events {
+        fn begin(&mut self, _: &Searcher, path: &Path) -> io::Result<()> {
+            self.events.push(format!("begin {}", path.display()));
+            Ok(())
+        }
+
+        fn matched(&mut self, _: &Searcher, m: &Match) -> io::Result<bool> {
+            self.events.push(format!("{}:{}", m.line_number, m.line));
+            Ok(self.stop_after.is_none_or(|n| self.events.len() < n))
+        }
+
+        fn context(&mut self, _: &Searcher, m: &Match) -> io::Result<bool> {
+            self.events.push(format!("{}-{}", m.line_number, m.line));
+            Ok(true)
+        }
+
+        fn context_break(&mut self, _: &Searcher) -> io::Result<bool> {
+            self.events.push(String::from("--"));
+            Ok(true)
+        }
+
+        fn finish(&mut self, _: &Searcher, _: &Path, stats: &SearchStats) -> io::Result<()> {
+            self.events.push(format!("end {}", stats.matches));
+            Ok(())
+        }
+    }
+
+    const CONTENTS: &str = "\
+one
+two match
+three
+four
+five
+six match
+seven
+eight match
+nine";
+
+    fn events(builder: SearcherBuilder, sink: &mut Events) -> Vec<String> {
+        let searcher = builder.pattern("match").build().unwrap();
+        searcher.search_reader(Path::new("a"), CONTENTS.as_bytes(), sink).unwrap();
+        sink.events.clone()
+    }
+
+    #[test]
+    fn reports_context_and_breaks() {
+        let builder = Searcher::builder().before_context(1).after_context(1);
+
+        assert_eq!(
+            vec![
+                "begin a", "1-one", "2:two match", "3-three", "--", "5-five", "6:six match",
+                "7-seven", "8:eight match", "9-nine", "end 3",
+            ],
+            events(builder, &mut Events::default())
+        );
+    }
+
+    #[test]
+    fn sink_stops_early() {
+        let mut sink = Events { stop_after: Some(3), ..Events::default() };
+
+        assert_eq!(vec!["begin a", "2:two match", "6:six match", "end 2"], events(Searcher::builder(), &mut sink));
+    }
+
+    #[test]
+    fn max_count_keeps_trailing_context() {
+        let builder = Searcher::builder().max_count(Some(2)).after_context(3);
+
+        assert_eq!(
+            vec!["begin a", "2:two match", "3-three", "4-four", "5-five", "6:six match", "7-seven", "8-eight match", "9-nine", "end 2"],
+            events(builder, &mut Events::default())
+        );
+    }
+
+    fn multiline_events(builder: SearcherBuilder) -> Vec<String> {
+        let searcher = builder.multiline(true).build().unwrap();
+        let mut sink = Events::default();
+        searcher.search_reader(Path::new("a"), CONTENTS.as_bytes(), &mut sink).unwrap();
+        sink.events
+    }
+
+    #[test]
+    fn multiline_blocks() {
+        let builder = Searcher::builder().pattern("three\nfour").pattern("seven\neight").after_context(1);
+
+        assert_eq!(
+            vec!["begin a", "3:three\nfour", "5-five", "--", "7:seven\neight match", "9-nine", "end 2"],
+            multiline_events(builder)
+        );
+    }
+
+    #[test]
+    fn multiline_merges_matches_on_shared_lines() {
+        let builder = Searcher::builder().patterns(["one\ntwo", "match\nthree", "nine"]);
+
+        assert_eq!(vec!["begin a", "1:one\ntwo match\nthree", "9:nine", "end 2"], multiline_events(builder));
+    }
+
+    #[test]
+    fn multiline_invert_and_max_count() {
+        let builder = Searcher::builder()
+            .pattern("two match\nthree\nfour\nfive\nsix")
+            .invert_match(true)
+            .max_count(Some(2));
+
+        assert_eq!(vec!["begin a", "1:one", "7:seven", "end 2"], multiline_events(builder));
+    }
+
+    #[test]
+    fn multiline_regex_anchors_at_lines() {
+        let builder = Searcher::builder().pattern(r"^t\w+ match$").regex(true);
+
+        assert_eq!(vec!["begin a", "2:two match", "end 1"], multiline_events(builder));
+
+        let searcher = Searcher::builder().pattern(r"^t\w+ match$").regex(true).multiline(true).build().unwrap();
+        let mut sink = Events::default();
+        searcher.search_reader(Path::new("a"), &b"one\r\ntwo match\r\nthree\r\n"[..], &mut sink).unwrap();
+        assert_eq!(vec!["begin a", "2:two match", "end 1"], sink.events);
+    }
+
+    #[test]
+    fn multiline_case_insensitive_many_matches() {
+        // Folding the rest of the input again for every match made this
+        // quadratic.
+        let contents = "Straße and FROG\n".repeat(40_000);
+
+        for patterns in [vec!["frog"], vec!["STRASSE", "toad"]] {
+            let searcher = Searcher::builder()
+                .patterns(patterns)
+                .case(CaseMode::Insensitive)
+                .multiline(true)
+                .build()
+                .unwrap();
+            let mut sink = Events::default();
+
+            let stats = searcher.search_reader(Path::new("a"), contents.as_bytes(), &mut sink).unwrap();
+
+            assert_eq!(40_000, stats.matches);
+        }
+    }
+
+    #[test]
+    fn binary_input() {
+        let searcher = Searcher::builder().pattern("match").binary(BinaryMode::Matches).build().unwrap();
+        let mut sink = Events::default();
+
+        let stats = searcher.search_reader(Path::new("a"), &b"\0match\n"[..], &mut sink).unwrap();
+
+        assert_eq!(SearchStats { matches: 1, binary: true }, stats);
+        assert_eq!(vec!["begin a", "end 1"], sink.events);
+    }
+
+    #[test]
+    fn binary_check_reads_only_the_head() {
+        let searcher = Searcher::builder().pattern("match").build().unwrap();
+        let mut contents = b"match\n".to_vec();
+        contents.extend(b"text\n".repeat(4_000));
+        contents.push(0);
+
+        // A slice hands over all of its bytes at once, like a memory map.
+        let stats = searcher.search_reader(Path::new("a"), &contents[..], &mut Events::default()).unwrap();
+
+        assert_eq!(SearchStats { matches: 1, binary: false }, stats);
+    }
+
+    #[test]
+    fn sorted_by_distance() {
+        let searcher = Searcher::builder()
+            .matcher(Box::new(Fuzzy::new(&["receive"], 2, true)))
+            .sort_by_distance(true)
+            .build()
+            .unwrap();
+        let mut sink = Events::default();
+
+        let contents = "recieve\nreceive\nrecive\nnothing\nreceive again\n";
+        searcher.search_reader(Path::new("a"), contents.as_bytes(), &mut sink).unwrap();
+
+        assert_eq!(vec!["begin a", "2:receive", "5:receive again", "3:recive", "1:recieve", "end 4"], sink.events);
+    }
+}
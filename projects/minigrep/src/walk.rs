@@ -0,0 +1,212 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+
+use crate::error::MinigrepError;
+
+/// Controls which files are visited when walking directories.
+#[derive(Debug, Default)]
+pub struct WalkOptions {
+    /// Also visit hidden files and directories (`--hidden`).
+    pub hidden: bool,
+    /// Don't read `.gitignore` and `.ignore` files (`--no-ignore`).
+    pub no_ignore: bool,
+    /// Gitignore-style globs; those starting with `!` exclude (`--glob`).
+    pub globs: Vec<String>,
+    /// Skip files larger than this many bytes (`--max-filesize`).
+    pub max_filesize: Option<u64>,
+}
+
+/// Expands the given paths into the list of files to search.
+///
+/// Files are returned as given; directories are walked recursively and their
+/// entries are visited in sorted order so the output is stable between runs.
+/// While walking, files matched by `.gitignore` or `.ignore` files, hidden
+/// files and files excluded by the globs in `options` are skipped.
+/// Symbolic links to directories are not followed. Files larger than
+/// `max_filesize` are skipped, whether given or found while walking.
+///
+/// Entries that can't be read while walking are added to `errors` and
+/// skipped; an invalid glob fails the whole walk.
+pub fn collect_files(
+    paths: &[String],
+    options: &WalkOptions,
+    errors: &mut Vec<MinigrepError>,
+) -> Result<Vec<PathBuf>, MinigrepError> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        let path = Path::new(path);
+
+        if path.is_dir() {
+            walk_dir(path, options, &mut files, errors)?;
+        } else if !too_large(path, options) {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    Ok(files)
+}
+
+fn walk_dir(
+    dir: &Path,
+    options: &WalkOptions,
+    files: &mut Vec<PathBuf>,
+    errors: &mut Vec<MinigrepError>,
+) -> Result<(), MinigrepError> {
+    let usage = |err: ignore::Error| MinigrepError::Usage(err.to_string());
+    let mut overrides = OverrideBuilder::new(dir);
+    for glob in &options.globs {
+        overrides.add(glob).map_err(usage)?;
+    }
+
+    let walker = WalkBuilder::new(dir)
+        .hidden(!options.hidden)
+        .ignore(!options.no_ignore)
+        .git_ignore(!options.no_ignore)
+        .git_global(!options.no_ignore)
+        .git_exclude(!options.no_ignore)
+        .parents(!options.no_ignore)
+        .require_git(false)
+        .max_filesize(options.max_filesize)
+        .overrides(overrides.build().map_err(usage)?)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                errors.push(MinigrepError::Walk(err));
+                continue;
+            }
+        };
+
+        let is_file = entry.file_type().is_some_and(|file_type| {
+            file_type.is_file() || (file_type.is_symlink() && entry.path().is_file())
+        });
+        if is_file {
+            files.push(entry.into_path());
+        }
+    }
+
+    Ok(())
+}
+
+fn too_large(path: &Path, options: &WalkOptions) -> bool {
+    options.max_filesize.is_some_and(|max| {
+        fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.len() > max)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    fn tree(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("minigrep-walk-{}-{}", name, std::process::id()));
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::create_dir_all(root.join("target").join("debug")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(root.join(".hidden.txt"), "").unwrap();
+        fs::write(root.join(".git").join("config"), "").unwrap();
+        fs::write(root.join("target").join("debug").join("out.rs"), "").unwrap();
+        fs::write(root.join("b").join("two.txt"), "").unwrap();
+        fs::write(root.join("b").join("lib.rs"), "").unwrap();
+        fs::write(root.join("a.txt"), "").unwrap();
+        fs::write(root.join("c.log"), "").unwrap();
+        fs::write(root.join("main.rs"), "").unwrap();
+        root
+    }
+
+    fn collect(root: &Path, options: &WalkOptions) -> Vec<PathBuf> {
+        let files = collect_files(&[root.to_string_lossy().into_owned()], options, &mut Vec::new()).unwrap();
+        files.iter().map(|file| file.strip_prefix(root).unwrap().to_path_buf()).collect()
+    }
+
+    #[test]
+    fn walks_directories_in_order() {
+        let root = tree("order");
+        let files = collect(&root, &WalkOptions::default());
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            vec![
+                PathBuf::from("a.txt"),
+                Path::new("b").join("lib.rs"),
+                Path::new("b").join("two.txt"),
+                PathBuf::from("main.rs"),
+            ],
+            files
+        );
+    }
+
+    #[test]
+    fn hidden_and_ignored_files() {
+        let root = tree("hidden");
+        let options = WalkOptions {
+            hidden: true,
+            no_ignore: true,
+            globs: vec![String::from("!.git/")],
+            ..WalkOptions::default()
+        };
+        let files = collect(&root, &options);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(files.contains(&PathBuf::from(".hidden.txt")));
+        assert!(files.contains(&PathBuf::from("c.log")));
+        assert!(files.contains(&Path::new("target").join("debug").join("out.rs")));
+        assert!(!files.contains(&Path::new(".git").join("config")));
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let root = tree("globs");
+        let options = WalkOptions {
+            no_ignore: true,
+            globs: vec![String::from("*.rs"), String::from("!target/**")],
+            ..WalkOptions::default()
+        };
+        let files = collect(&root, &options);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(vec![Path::new("b").join("lib.rs"), PathBuf::from("main.rs")], files);
+    }
+
+    #[test]
+    fn max_filesize() {
+        let root = tree("size");
+        fs::write(root.join("a.txt"), "x".repeat(100)).unwrap();
+        let options = WalkOptions { max_filesize: Some(10), ..WalkOptions::default() };
+        let walked = collect(&root, &options);
+        let given = collect_files(&[root.join("a.txt").to_string_lossy().into_owned()], &options, &mut Vec::new());
+        let given = given.unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(!walked.contains(&PathBuf::from("a.txt")));
+        assert!(walked.contains(&PathBuf::from("main.rs")));
+        assert!(given.is_empty());
+    }
+
+    #[test]
+    fn invalid_glob() {
+        let root = tree("bad-glob");
+        let options = WalkOptions { globs: vec![String::from("a{b")], ..WalkOptions::default() };
+        let result = collect_files(&[root.to_string_lossy().into_owned()], &options, &mut Vec::new());
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(matches!(result, Err(MinigrepError::Usage(_))));
+    }
+
+    #[test]
+    fn explicit_files_are_kept() {
+        let paths = [String::from("-"), String::from(".hidden")];
+        let files = collect_files(&paths, &WalkOptions::default(), &mut Vec::new()).unwrap();
+
+        assert_eq!(vec![PathBuf::from("-"), PathBuf::from(".hidden")], files);
+    }
+}
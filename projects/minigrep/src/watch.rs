@@ -0,0 +1,415 @@
+//! Watch mode (`--watch`): keep following the searched files and print the
+//! selected lines appended to them, like `tail -f | grep`.
+//!
+//! Files are followed from where they ended when watching started. Files
+//! that show up later are searched from their start, and so is a file that
+//! shrinks, as when a log is truncated. Only complete lines are searched: a
+//! line still being written is picked up once its newline arrives. Appended
+//! text is searched as it is, without decompressing or decoding it.
+//!
+//! On Linux, changes are noticed through inotify watches on the directories
+//! holding the files. Elsewhere, or when inotify can't be used, the files
+//! are checked every `POLL_INTERVAL`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::MinigrepError;
+use crate::printer::Printer;
+use crate::searcher::{SearchStats, Searcher, Sink};
+use crate::{walk, Config, Match};
+
+/// How often files are checked when they can't be watched with inotify.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Follows `files`, the files named by `config`, until an error stops it,
+/// printing the appended lines `searcher` selects. Files that can't be read
+/// are passed to `report` and retried on the next change.
+///
+/// Only returns on an error that isn't about a single file.
+pub fn follow<W: Write>(
+    files: Vec<PathBuf>,
+    config: &Config,
+    searcher: &Searcher,
+    printer: &mut Printer<W>,
+    report: &mut dyn FnMut(MinigrepError),
+) -> Result<Infallible, MinigrepError> {
+    let mut follower = Follower::new(&files, report);
+    let mut wakeup = Wakeup::new();
+    wakeup.watch(dirs(&config.filenames, &files));
+
+    loop {
+        wakeup.wait();
+
+        // Walk errors were reported by the first walk; don't repeat them on
+        // every change.
+        let files = walk::collect_files(&config.filenames, &config.walk, &mut Vec::new())?;
+        wakeup.watch(dirs(&config.filenames, &files));
+
+        follower.update(&files, searcher, printer, report)?;
+        printer.flush().map_err(MinigrepError::Output)?;
+    }
+}
+
+/// How far a file has been searched.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Position {
+    /// Byte offset just after the last complete line searched.
+    offset: u64,
+    /// Number of lines before `offset`.
+    lines: usize,
+    /// Whether reading the file failed last time, so it isn't reported again.
+    failed: bool,
+}
+
+/// The files being followed and how far each has been searched.
+#[derive(Debug, Default)]
+pub struct Follower {
+    positions: BTreeMap<PathBuf, Position>,
+}
+
+impl Follower {
+    /// Starts following `files` from their current end. Files that can't be
+    /// read are passed to `report` and will be searched from their start.
+    pub fn new(files: &[PathBuf], report: &mut dyn FnMut(MinigrepError)) -> Follower {
+        let mut positions = BTreeMap::new();
+
+        for path in files {
+            let position = end_of(path).unwrap_or_else(|err| {
+                report(MinigrepError::read(path, err));
+                Position { failed: true, ..Position::default() }
+            });
+            positions.insert(path.clone(), position);
+        }
+
+        Follower { positions }
+    }
+
+    /// Searches what was appended to each of `files` since the last call and
+    /// forgets the files no longer listed. Returns the number of selected
+    /// lines.
+    pub fn update<W: Write>(
+        &mut self,
+        files: &[PathBuf],
+        searcher: &Searcher,
+        printer: &mut Printer<W>,
+        report: &mut dyn FnMut(MinigrepError),
+    ) -> Result<u64, MinigrepError> {
+        let mut positions = BTreeMap::new();
+        let mut selected = 0;
+
+        for path in files {
+            let mut position = self.positions.remove(path).unwrap_or_default();
+
+            match search_appended(path, &mut position, searcher, printer) {
+                Ok(count) => {
+                    selected += count;
+                    position.failed = false;
+                }
+                Err(err) if err.is_per_file() => {
+                    if !position.failed {
+                        report(err);
+                    }
+                    position.failed = true;
+                }
+                Err(err) => return Err(err),
+            }
+
+            positions.insert(path.clone(), position);
+        }
+
+        self.positions = positions;
+        Ok(selected)
+    }
+}
+
+/// Returns the position after the last complete line of the file at `path`.
+fn end_of(path: &Path) -> io::Result<Position> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut position = Position::default();
+    let mut read = 0;
+
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => return Ok(position),
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        for (i, _) in buf[..n].iter().enumerate().filter(|(_, &byte)| byte == b'\n') {
+            position.lines += 1;
+            position.offset = read + i as u64 + 1;
+        }
+        read += n as u64;
+    }
+}
+
+/// Searches the complete lines appended to `path` after `position` and moves
+/// `position` past them.
+fn search_appended<W: Write>(
+    path: &Path,
+    position: &mut Position,
+    searcher: &Searcher,
+    printer: &mut Printer<W>,
+) -> Result<u64, MinigrepError> {
+    let read_error = |err| MinigrepError::read(path, err);
+    let mut file = File::open(path).map_err(read_error)?;
+    let len = file.metadata().map_err(read_error)?.len();
+
+    if len < position.offset {
+        // Truncated: everything in it now is new.
+        *position = Position::default();
+    }
+    if len == position.offset {
+        return Ok(0);
+    }
+
+    let mut appended = Vec::new();
+    file.seek(SeekFrom::Start(position.offset)).map_err(read_error)?;
+    file.take(len - position.offset).read_to_end(&mut appended).map_err(read_error)?;
+
+    // Leave a line that's still being written for later.
+    match appended.iter().rposition(|&byte| byte == b'\n') {
+        Some(end) => appended.truncate(end + 1),
+        None => return Ok(0),
+    }
+
+    let mut sink = Shifted { sink: printer, lines: position.lines, offset: position.offset as usize };
+    let stats = searcher.search_reader(path, appended.as_slice(), &mut sink)?;
+
+    position.offset += appended.len() as u64;
+    position.lines += appended.iter().filter(|&&byte| byte == b'\n').count();
+    Ok(stats.matches)
+}
+
+/// Passes lines on to `sink` with their line numbers and offsets counted
+/// from the start of the file rather than of the appended text.
+struct Shifted<'a, S> {
+    sink: &'a mut S,
+    lines: usize,
+    offset: usize,
+}
+
+impl<S> Shifted<'_, S> {
+    fn shift<'m>(&self, m: &Match<'m>) -> Match<'m> {
+        Match { line_number: m.line_number + self.lines, offset: m.offset + self.offset, line: m.line }
+    }
+}
+
+impl<S: Sink> Sink for Shifted<'_, S> {
+    fn begin(&mut self, searcher: &Searcher, path: &Path) -> io::Result<()> {
+        self.sink.begin(searcher, path)
+    }
+
+    fn matched(&mut self, searcher: &Searcher, m: &Match) -> io::Result<bool> {
+        let m = self.shift(m);
+        self.sink.matched(searcher, &m)
+    }
+
+    fn context(&mut self, searcher: &Searcher, line: &Match) -> io::Result<bool> {
+        let line = self.shift(line);
+        self.sink.context(searcher, &line)
+    }
+
+    fn context_break(&mut self, searcher: &Searcher) -> io::Result<bool> {
+        self.sink.context_break(searcher)
+    }
+
+    fn finish(&mut self, searcher: &Searcher, path: &Path, stats: &SearchStats) -> io::Result<()> {
+        self.sink.finish(searcher, path, stats)
+    }
+}
+
+/// The directories whose changes matter: those given and those holding the
+/// files.
+fn dirs(filenames: &[String], files: &[PathBuf]) -> BTreeSet<PathBuf> {
+    let parent = |path: &Path| match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    filenames.iter()
+        .map(Path::new)
+        .map(|path| if path.is_dir() { path.to_path_buf() } else { parent(path) })
+        .chain(files.iter().map(|path| parent(path)))
+        .collect()
+}
+
+/// Waits for the next change to the watched directories.
+enum Wakeup {
+    #[cfg(target_os = "linux")]
+    Inotify(inotify_watch::Watches),
+    Poll,
+}
+
+impl Wakeup {
+    fn new() -> Wakeup {
+        #[cfg(target_os = "linux")]
+        if let Ok(watches) = inotify_watch::Watches::new() {
+            return Wakeup::Inotify(watches);
+        }
+        Wakeup::Poll
+    }
+
+    /// Makes changes in `dirs` end the next wait, falling back to polling if
+    /// they can't be watched.
+    fn watch(&mut self, dirs: BTreeSet<PathBuf>) {
+        match self {
+            #[cfg(target_os = "linux")]
+            Wakeup::Inotify(watches) => {
+                if watches.add(dirs).is_err() {
+                    *self = Wakeup::Poll;
+                }
+            }
+            Wakeup::Poll => {}
+        }
+    }
+
+    fn wait(&mut self) {
+        match self {
+            #[cfg(target_os = "linux")]
+            Wakeup::Inotify(watches) => {
+                if watches.wait().is_err() {
+                    *self = Wakeup::Poll;
+                }
+            }
+            Wakeup::Poll => thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify_watch {
+    use std::collections::{BTreeSet, HashMap};
+    use std::io;
+    use std::path::PathBuf;
+
+    use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+    pub struct Watches {
+        inotify: Inotify,
+        dirs: HashMap<WatchDescriptor, PathBuf>,
+        watched: BTreeSet<PathBuf>,
+        buffer: Vec<u8>,
+    }
+
+    impl Watches {
+        pub fn new() -> io::Result<Watches> {
+            Ok(Watches {
+                inotify: Inotify::init()?,
+                dirs: HashMap::new(),
+                watched: BTreeSet::new(),
+                buffer: vec![0; 4096],
+            })
+        }
+
+        /// Watches the directories of `dirs` not watched yet. Directories that
+        /// no longer exist are skipped; other errors, such as running out of
+        /// watches, are returned.
+        pub fn add(&mut self, dirs: BTreeSet<PathBuf>) -> io::Result<()> {
+            let mask = WatchMask::MODIFY | WatchMask::CLOSE_WRITE | WatchMask::CREATE
+                | WatchMask::MOVED_TO | WatchMask::DELETE;
+
+            for dir in dirs {
+                if self.watched.contains(&dir) {
+                    continue;
+                }
+                match self.inotify.watches().add(&dir, mask) {
+                    Ok(wd) => {
+                        self.dirs.insert(wd, dir.clone());
+                        self.watched.insert(dir);
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(())
+        }
+
+        /// Blocks until something changes in a watched directory. New
+        /// subdirectories are watched too, so files created in them later
+        /// are noticed.
+        pub fn wait(&mut self) -> io::Result<()> {
+            let mut created = BTreeSet::new();
+
+            for event in self.inotify.read_events_blocking(&mut self.buffer)? {
+                if event.mask.contains(EventMask::IGNORED) {
+                    // The directory was removed; watch it again if it's
+                    // recreated.
+                    if let Some(dir) = self.dirs.remove(&event.wd) {
+                        self.watched.remove(&dir);
+                    }
+                } else if event.mask.contains(EventMask::ISDIR) {
+                    if let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) {
+                        created.insert(dir.join(name));
+                    }
+                }
+            }
+
+            self.add(created)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs::{self, OpenOptions};
+
+    fn append(path: &Path, text: &str) {
+        OpenOptions::new().append(true).open(path).unwrap().write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn prints_only_appended_lines() {
+        let root = env::temp_dir().join(format!("minigrep-watch-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let log = root.join("app.log");
+        fs::write(&log, "frog one\ntoad two\nfrog thr").unwrap();
+
+        let searcher = Searcher::builder().pattern("frog").build().unwrap();
+        let mut printer = Printer::new(Vec::new());
+        printer.line_number = true;
+        let mut errors = Vec::new();
+        let mut report = |err| errors.push(err);
+
+        let mut files = vec![log.clone()];
+        let mut follower = Follower::new(&files, &mut report);
+        assert_eq!(0, follower.update(&files, &searcher, &mut printer, &mut report).unwrap());
+
+        append(&log, "ee\nfrog four");
+        assert_eq!(1, follower.update(&files, &searcher, &mut printer, &mut report).unwrap());
+
+        let new = root.join("new.log");
+        fs::write(&new, "a frog\n").unwrap();
+        files.push(new);
+        fs::write(&log, "frog again\n").unwrap();
+        assert_eq!(2, follower.update(&files, &searcher, &mut printer, &mut report).unwrap());
+
+        files.push(root.join("missing.log"));
+        follower.update(&files, &searcher, &mut printer, &mut report).unwrap();
+        follower.update(&files, &searcher, &mut printer, &mut report).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(1, errors.len());
+        assert_eq!("3:frog three\n1:frog again\n1:a frog\n", String::from_utf8(printer.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn watched_dirs() {
+        let files = [PathBuf::from("app.log"), PathBuf::from("logs/a/b.log")];
+
+        assert_eq!(
+            BTreeSet::from([PathBuf::from("."), PathBuf::from("logs/a")]),
+            dirs(&[String::from("app.log")], &files)
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::process::Command;
+
+fn minigrep(args: &[&str]) -> (String, String, Option<i32>) {
+    let output = Command::new(env!("CARGO_BIN_EXE_minigrep"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env_remove("CASE_INSENSITIVE")
+        .env("MINIGREP_CONFIG", "")
+        .args(args)
+        .output()
+        .unwrap();
+
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+        output.status.code(),
+    )
+}
+
+#[test]
+fn search_continues_after_unreadable_file() {
+    let (stdout, stderr, code) = minigrep(&["frog", "no/such/file.txt", "poem.txt"]);
+
+    assert_eq!("poem.txt:How public, like a frog\n", stdout);
+    assert!(stderr.starts_with("minigrep: no/such/file.txt: "));
+    assert_eq!(Some(2), code);
+}
+
+#[test]
+fn no_messages() {
+    let (stdout, stderr, code) = minigrep(&["--no-messages", "toad", "no/such/file.txt", "poem.txt"]);
+
+    assert_eq!("", stdout);
+    assert_eq!("", stderr);
+    assert_eq!(Some(2), code);
+}
+
+#[test]
+fn quiet_selection_wins_over_errors() {
+    let (stdout, stderr, code) = minigrep(&["-q", "frog", "no/such/file.txt", "poem.txt"]);
+
+    assert_eq!("", stdout);
+    assert!(stderr.starts_with("minigrep: no/such/file.txt: "));
+    assert_eq!(Some(0), code);
+
+    let (_, _, code) = minigrep(&["-q", "toad", "no/such/file.txt", "poem.txt"]);
+    assert_eq!(Some(2), code);
+}
+
+#[test]
+fn decode_errors() {
+    let (_, stderr, code) = minigrep(&["-z", "frog", "poem.txt"]);
+
+    assert!(stderr.starts_with("minigrep: poem.txt: couldn't decode: "));
+    assert_eq!(Some(2), code);
+}
+
+#[test]
+fn invalid_pattern() {
+    let (_, stderr, code) = minigrep(&["--regex", "(frog", "poem.txt"]);
+
+    assert!(stderr.starts_with("Application error: invalid pattern: "));
+    assert_eq!(Some(2), code);
+}
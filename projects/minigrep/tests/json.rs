@@ -0,0 +1,45 @@
+use std::fs;
+use std::process::Command;
+
+fn minigrep_json(args: &[&str]) -> (String, Option<i32>) {
+    let output = Command::new(env!("CARGO_BIN_EXE_minigrep"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env_remove("CASE_INSENSITIVE")
+        .env("MINIGREP_CONFIG", "")
+        .arg("--json")
+        .args(args)
+        .output()
+        .unwrap();
+
+    (String::from_utf8(output.stdout).unwrap(), output.status.code())
+}
+
+fn fixture(name: &str) -> String {
+    fs::read_to_string(format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+}
+
+#[test]
+fn match_records() {
+    assert_eq!((fixture("frog.jsonl"), Some(0)), minigrep_json(&["frog", "poem.txt"]));
+}
+
+#[test]
+fn context_records() {
+    assert_eq!(
+        (fixture("context.jsonl"), Some(0)),
+        minigrep_json(&["-i", "-C1", "-e", "nobody", "-e", "BOG", "poem.txt"])
+    );
+}
+
+#[test]
+fn inverted_lines_have_no_submatches() {
+    assert_eq!(
+        (fixture("invert.jsonl"), Some(0)),
+        minigrep_json(&["-v", "-e", "の", "-e", "a", "-e", "e", "-e", "o", "poem.txt"])
+    );
+}
+
+#[test]
+fn summary_without_matches() {
+    assert_eq!((fixture("no_match.jsonl"), Some(1)), minigrep_json(&["zzz", "poem.txt"]));
+}